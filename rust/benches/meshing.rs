@@ -0,0 +1,26 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use game::blocky_mesh::{chunk_ground, chunk_water, mesh};
+use game::smooth_mesh::{binary_density, surface_nets};
+use game::voxel_storage::VoxelWorld;
+
+fn blocky(c: &mut Criterion) {
+    let world = VoxelWorld::gen(-1..2, -1..2);
+    let faces = world.ground[&[0, 0]].visible_faces();
+    c.bench_function("blocky/mesh", |b| b.iter(|| mesh(&faces, &[])));
+    c.bench_function("blocky/chunk_ground", |b| {
+        b.iter(|| chunk_ground(&world, [0, 0]))
+    });
+    c.bench_function("blocky/chunk_water", |b| {
+        b.iter(|| chunk_water(&world, [0, 0], 0.1))
+    });
+}
+
+fn smooth(c: &mut Criterion) {
+    let world = VoxelWorld::gen(0..1, 0..1);
+    c.bench_function("smooth/surface_nets", |b| {
+        b.iter(|| surface_nets(|p| binary_density(p[1] < 0 || world.is_ground(p))))
+    });
+}
+
+criterion_group!(benches, blocky, smooth);
+criterion_main!(benches);
@@ -0,0 +1,39 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use game::save::{load_world, save_world};
+use game::voxel_storage::VoxelWorld;
+use game::water_sim::simulate_water;
+
+/// Steps on freshly generated worlds, where most water is falling, and on worlds that already
+/// ran `SETTLE_STEPS` steps and mostly spread sideways.
+const SETTLE_STEPS: u8 = 255;
+/// The step counter selects the flow direction of a step and repeats after 8 steps, so every
+/// iteration runs a full cycle.
+const CYCLE: u8 = 8;
+
+fn water_steps(c: &mut Criterion) {
+    let mut group = c.benchmark_group("simulate_water");
+    group.sample_size(20);
+    for side in [1i8, 2, 4] {
+        let fresh = VoxelWorld::gen(0..side, 0..side);
+        let mut settled = VoxelWorld::gen(0..side, 0..side);
+        for i in 0..SETTLE_STEPS {
+            simulate_water(&mut settled, i);
+        }
+        for (state, world) in [("fresh", fresh), ("settled", settled)] {
+            // every iteration starts from a copy of the same world
+            let bytes = save_world(&world);
+            let id = BenchmarkId::new(state, format!("{side}x{side}, {CYCLE} steps"));
+            group.bench_function(id, |b| {
+                b.iter_batched_ref(
+                    || load_world(&bytes).unwrap(),
+                    |world| (0..CYCLE).map(|i| simulate_water(world, i)).sum::<u64>(),
+                    BatchSize::LargeInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, water_steps);
+criterion_main!(benches);
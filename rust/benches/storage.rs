@@ -0,0 +1,44 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use game::save::{load_world, save_world};
+use game::voxel_storage::{VoxelStorage, VoxelWorld};
+
+fn chunks() -> Vec<(&'static str, VoxelStorage)> {
+    let empty = VoxelStorage::empty();
+    let mut full = VoxelStorage::empty();
+    for x in 0..64 {
+        for z in 0..64 {
+            full.set_pillar([x, z], u64::MAX);
+        }
+    }
+    let noise = VoxelWorld::gen(0..1, 0..1).ground.remove(&[0, 0]).unwrap();
+    vec![("empty", empty), ("full", full), ("noise", noise)]
+}
+
+fn faces(c: &mut Criterion) {
+    let mut group = c.benchmark_group("visible_faces");
+    for (name, chunk) in chunks() {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &chunk, |b, chunk| {
+            b.iter(|| chunk.visible_faces())
+        });
+    }
+    group.finish();
+}
+
+fn save_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("save");
+    for side in [1i8, 4] {
+        let world = VoxelWorld::gen(0..side, 0..side);
+        let bytes = save_world(&world);
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("save_world", side), &world, |b, world| {
+            b.iter(|| save_world(world))
+        });
+        group.bench_with_input(BenchmarkId::new("load_world", side), &bytes, |b, bytes| {
+            b.iter(|| load_world(black_box(bytes)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, faces, save_load);
+criterion_main!(benches);
@@ -0,0 +1,272 @@
+//! Generates, simulates and exports worlds without Godot, e.g. for long simulation runs on
+//! machines without a display. Run with `--help` for the options.
+
+use std::ops::Range;
+use std::process::exit;
+use std::time::Instant;
+
+use game::caves::CaveSettings;
+use game::erosion::ErosionSettings;
+use game::heightmap::{world_from_heightmap, Heightmap, HeightmapSettings};
+use game::hydrology::RiverSettings;
+use game::mesh_export::{chunk_meshes, merge, write_glb, write_gltf, write_obj};
+use game::prefab::PrefabSettings;
+use game::save::{load_world, save_world};
+use game::vox::{write_vox, VoxPalette};
+use game::voxel_storage::{BiomeSettings, GenSettings, VoxelWorld};
+use game::water_sim::simulate_water;
+use game::water_stats::WaterStatistics;
+
+const USAGE: &str = "usage: worldtool [options]
+
+world, generated by noise unless one of these is given:
+  --chunks N            chunks per side of the generated world, at most 127 (default 4)
+  --erosion N           erode the generated terrain with N droplets per chunk
+  --caves               carve cheese and worm caves into the generated ground
+  --biomes              blend desert, plains and mountain terrain with their own water levels
+  --rivers N            carve N rivers from springs and fill the lakes of the terrain
+  --prefabs             place trees, ruins and bridges on the generated terrain
+  --load PATH           world save file
+  --heightmap PATH      PNG, PGM or square raw 16 bit heightmap, one pillar per pixel
+  --water-mask PATH     image of the same size, water where brighter than half
+  --scale F             ground height of a white heightmap pixel (default 40)
+  --offset F            ground height of a black heightmap pixel (default 0)
+  --water-level N       height of the water in the water mask (default 0)
+
+simulation:
+  --steps N             water simulation steps (default 0)
+  --stats-every N       print statistics every N steps, 0 only at the end (default 0)
+  --basins N            basins listed in the statistics (default 5)
+
+output:
+  --save PATH           world save file
+  --export PATH         chunk meshes as .obj, .gltf or .glb
+  --merged              one mesh per material instead of one per chunk
+  --water-lowering F    how far water surfaces lie below the top of their cell (default 0.1)
+  --vox PATH            MagicaVoxel file";
+
+struct Options {
+    chunks: u8,
+    erosion: Option<usize>,
+    caves: bool,
+    biomes: bool,
+    rivers: Option<usize>,
+    prefabs: bool,
+    load: Option<String>,
+    heightmap: Option<String>,
+    water_mask: Option<String>,
+    scale: f32,
+    offset: f32,
+    water_level: u8,
+    steps: u64,
+    stats_every: u64,
+    basins: usize,
+    save: Option<String>,
+    export: Option<String>,
+    merged: bool,
+    water_lowering: f32,
+    vox: Option<String>,
+}
+
+fn main() {
+    let options = parse(std::env::args().skip(1)).unwrap_or_else(|message| fail(&message));
+
+    let start = Instant::now();
+    let mut world = create_world(&options);
+    println!(
+        "world: {} x {} chunks in {:.2?}",
+        world.xs.len(),
+        world.zs.len(),
+        start.elapsed()
+    );
+    print!(
+        "{}",
+        WaterStatistics::collect(&world, 0).summary(options.basins)
+    );
+
+    let start = Instant::now();
+    let mut moving_cells = 0;
+    for i in 0..options.steps {
+        moving_cells = simulate_water(&mut world, i as u8);
+        let step = i + 1;
+        if options.stats_every > 0 && step % options.stats_every == 0 && step < options.steps {
+            println!("step {step} after {:.2?}", start.elapsed());
+            print!(
+                "{}",
+                WaterStatistics::collect(&world, moving_cells).summary(options.basins)
+            );
+        }
+    }
+    if options.steps > 0 {
+        let elapsed = start.elapsed();
+        println!(
+            "{} steps in {:.2?}, {:.2?} per step",
+            options.steps,
+            elapsed,
+            elapsed.div_f64(options.steps as f64)
+        );
+        print!(
+            "{}",
+            WaterStatistics::collect(&world, moving_cells).summary(options.basins)
+        );
+    }
+
+    if let Some(path) = &options.save {
+        write(path, &save_world(&world));
+    }
+    if let Some(path) = &options.export {
+        let start = Instant::now();
+        let mut meshes = chunk_meshes(&world, options.water_lowering);
+        if options.merged {
+            meshes = merge(meshes);
+        }
+        let bytes = match path.rsplit('.').next() {
+            Some("obj") => write_obj(&meshes).into_bytes(),
+            Some("gltf") => write_gltf(&meshes).into_bytes(),
+            Some("glb") => write_glb(&meshes),
+            _ => fail(&format!("unknown mesh format of {path}")),
+        };
+        write(path, &bytes);
+        println!(
+            "exported {} meshes in {:.2?}",
+            meshes.len(),
+            start.elapsed()
+        );
+    }
+    if let Some(path) = &options.vox {
+        write(path, &write_vox(&world, &VoxPalette::default()));
+    }
+}
+
+fn create_world(options: &Options) -> VoxelWorld {
+    if let Some(path) = &options.load {
+        return load_world(&read(path)).unwrap_or_else(|| fail(&format!("invalid save {path}")));
+    }
+    if let Some(path) = &options.heightmap {
+        let decode = |path: &str| {
+            Heightmap::decode(&read(path))
+                .unwrap_or_else(|| fail(&format!("invalid heightmap {path}")))
+        };
+        let heightmap = decode(path);
+        let water_mask = options.water_mask.as_deref().map(decode);
+        let chunks = |pixels: usize| {
+            i8::try_from(pixels.div_ceil(64))
+                .unwrap_or_else(|_| fail("the heightmap is wider than 127 chunks"))
+        };
+        return world_from_heightmap(
+            centered(chunks(heightmap.width)),
+            centered(chunks(heightmap.height)),
+            &heightmap,
+            water_mask.as_ref(),
+            &HeightmapSettings {
+                scale: options.scale,
+                offset: options.offset,
+                water_level: options.water_level,
+            },
+        )
+        .unwrap_or_else(|| fail("the water mask differs in size from the heightmap"));
+    }
+    let chunks = centered(options.chunks as i8);
+    let settings = GenSettings {
+        erosion: options.erosion.map(|iterations| ErosionSettings {
+            iterations,
+            ..Default::default()
+        }),
+        caves: options.caves.then(CaveSettings::default),
+        biomes: options.biomes.then(BiomeSettings::default),
+        rivers: options.rivers.map(|springs| RiverSettings {
+            springs,
+            ..Default::default()
+        }),
+        prefabs: options.prefabs.then(PrefabSettings::default),
+    };
+    VoxelWorld::generate(chunks.clone(), chunks, &settings)
+}
+
+/// range of `count` chunks around the origin
+fn centered(count: i8) -> Range<i8> {
+    -(count / 2)..count - count / 2
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options {
+        chunks: 4,
+        erosion: None,
+        caves: false,
+        biomes: false,
+        rivers: None,
+        prefabs: false,
+        load: None,
+        heightmap: None,
+        water_mask: None,
+        scale: 40.0,
+        offset: 0.0,
+        water_level: 0,
+        steps: 0,
+        stats_every: 0,
+        basins: 5,
+        save: None,
+        export: None,
+        merged: false,
+        water_lowering: 0.1,
+        vox: None,
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value of {arg}"));
+        match arg.as_str() {
+            "--chunks" => {
+                options.chunks = number(&arg, value()?)?;
+                if options.chunks == 0 {
+                    return Err(format!("{arg} needs at least one chunk"));
+                }
+                if options.chunks > i8::MAX as u8 {
+                    return Err(format!("{arg} takes at most {} chunks", i8::MAX));
+                }
+            }
+            "--erosion" => options.erosion = Some(number(&arg, value()?)?),
+            "--caves" => options.caves = true,
+            "--biomes" => options.biomes = true,
+            "--rivers" => options.rivers = Some(number(&arg, value()?)?),
+            "--prefabs" => options.prefabs = true,
+            "--load" => options.load = Some(value()?),
+            "--heightmap" => options.heightmap = Some(value()?),
+            "--water-mask" => options.water_mask = Some(value()?),
+            "--scale" => options.scale = number(&arg, value()?)?,
+            "--offset" => options.offset = number(&arg, value()?)?,
+            "--water-level" => options.water_level = number::<u8>(&arg, value()?)?.min(64),
+            "--steps" => options.steps = number(&arg, value()?)?,
+            "--stats-every" => options.stats_every = number(&arg, value()?)?,
+            "--basins" => options.basins = number(&arg, value()?)?,
+            "--save" => options.save = Some(value()?),
+            "--export" => options.export = Some(value()?),
+            "--merged" => options.merged = true,
+            "--water-lowering" => options.water_lowering = number(&arg, value()?)?,
+            "--vox" => options.vox = Some(value()?),
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                exit(0);
+            }
+            _ => return Err(format!("unknown option {arg}")),
+        }
+    }
+    Ok(options)
+}
+
+fn number<T: std::str::FromStr>(arg: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {value} of {arg}"))
+}
+
+fn read(path: &str) -> Vec<u8> {
+    std::fs::read(path).unwrap_or_else(|e| fail(&format!("could not read {path}: {e}")))
+}
+
+fn write(path: &str, bytes: &[u8]) {
+    std::fs::write(path, bytes).unwrap_or_else(|e| fail(&format!("could not write {path}: {e}")));
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{message}\n\n{USAGE}");
+    exit(1)
+}
@@ -0,0 +1,382 @@
+use crate::mesh_data::{MeshBuilder, MeshData, Vertex};
+use crate::voxel_storage::{Faces, VoxelWorld};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    Top,
+    Bottom,
+    Left,
+    Right,
+    Back,
+    Front,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 6] = [
+        Direction::Top,
+        Direction::Bottom,
+        Direction::Left,
+        Direction::Right,
+        Direction::Back,
+        Direction::Front,
+    ];
+
+    pub fn normal(self) -> [i32; 3] {
+        match self {
+            Direction::Top => [0, 1, 0],
+            Direction::Bottom => [0, -1, 0],
+            Direction::Left => [-1, 0, 0],
+            Direction::Right => [1, 0, 0],
+            Direction::Back => [0, 0, 1],
+            Direction::Front => [0, 0, -1],
+        }
+    }
+
+    /// axes spanning the face, u and v
+    pub fn axes(self) -> [usize; 2] {
+        match self {
+            Direction::Top | Direction::Bottom => [0, 2],
+            Direction::Left | Direction::Right => [1, 2],
+            Direction::Back | Direction::Front => [0, 1],
+        }
+    }
+
+    /// corners of the face as offsets from the voxel origin, in the order `MeshBuilder::quad`
+    /// expects them
+    pub fn corners(self) -> [[i32; 3]; 4] {
+        let [u, v] = self.axes();
+        let n = self.normal();
+        let base = n.map(|c| c.max(0));
+        // top, bottom, left and right faces have u and v swapped relative to back and front
+        let order = match self {
+            Direction::Back | Direction::Front => [[0, 0], [0, 1], [1, 0], [1, 1]],
+            _ => [[0, 0], [1, 0], [0, 1], [1, 1]],
+        };
+        order.map(|[du, dv]| {
+            let mut c = base;
+            c[u] += du;
+            c[v] += dv;
+            c
+        })
+    }
+
+    /// bottom, right and front faces need the opposite winding of their corners
+    pub fn reversed(self) -> bool {
+        matches!(
+            self,
+            Direction::Bottom | Direction::Right | Direction::Front
+        )
+    }
+
+    pub fn of(self, faces: &Faces) -> &[[u8; 3]] {
+        match self {
+            Direction::Top => &faces.top,
+            Direction::Bottom => &faces.bottom,
+            Direction::Left => &faces.left,
+            Direction::Right => &faces.right,
+            Direction::Back => &faces.back,
+            Direction::Front => &faces.front,
+        }
+    }
+}
+
+pub struct Face {
+    /// voxel the face belongs to, relative to the chunk
+    pub voxel: [i32; 3],
+    pub direction: Direction,
+}
+
+/// fills in vertex attributes of the four corners of a face, in the order of
+/// `Direction::corners`
+pub trait FaceAttribute {
+    fn apply(&self, face: &Face, corners: &mut [Vertex; 4]);
+}
+
+/// texture coordinates projected onto the plane of the face, one unit per voxel
+pub struct PlanarUv;
+
+impl FaceAttribute for PlanarUv {
+    fn apply(&self, face: &Face, corners: &mut [Vertex; 4]) {
+        let [u, v] = face.direction.axes();
+        for c in corners.iter_mut() {
+            c.uv = [c.position[u], c.position[v]];
+        }
+    }
+}
+
+/// brightness of the ambient occlusion levels, 0 is a fully occluded corner
+const AO_LEVELS: [f32; 4] = [0.4, 0.6, 0.8, 1.0];
+
+/// Ambient occlusion in the vertex colors, the classic 3 neighbour rule: per vertex the two side
+/// voxels and the corner voxel in front of the face. The occluder tells whether a voxel darkens
+/// the corners of its neighbours, positions are relative to the chunk and can lie outside of it.
+pub struct AmbientOcclusion<F: Fn([i32; 3]) -> bool>(pub F);
+
+impl<F: Fn([i32; 3]) -> bool> FaceAttribute for AmbientOcclusion<F> {
+    fn apply(&self, face: &Face, corners: &mut [Vertex; 4]) {
+        let [u, v] = face.direction.axes();
+        let n = face.direction.normal();
+        let front = [0, 1, 2].map(|i| face.voxel[i] + n[i]);
+        for (corner, offset) in corners.iter_mut().zip(face.direction.corners()) {
+            let mut side_u = front;
+            side_u[u] += offset[u] * 2 - 1;
+            let mut side_v = front;
+            side_v[v] += offset[v] * 2 - 1;
+            let mut diagonal = side_u;
+            diagonal[v] = side_v[v];
+            let (side_u, side_v, diagonal) = (self.0(side_u), self.0(side_v), self.0(diagonal));
+            let level = if side_u && side_v {
+                0
+            } else {
+                3 - side_u as usize - side_v as usize - diagonal as usize
+            };
+            let b = AO_LEVELS[level];
+            corner.color = [b, b, b, 1.0];
+        }
+    }
+}
+
+/// One quad per face with shared vertices. Positions, normals and tangents come from the face,
+/// everything else from `attributes`, applied in order.
+pub fn mesh(faces: &Faces, attributes: &[&dyn FaceAttribute]) -> MeshData {
+    let mut builder = MeshBuilder::default();
+    for direction in Direction::ALL {
+        let normal = direction.normal().map(|n| n as f32);
+        let tangent = tangent(direction);
+        for &[x, y, z] in direction.of(faces) {
+            let voxel = [x as i32, y as i32, z as i32];
+            let mut corners = direction.corners().map(|c| {
+                let mut vertex = Vertex::new([0, 1, 2].map(|i| (voxel[i] + c[i]) as f32), normal);
+                vertex.tangent = tangent;
+                vertex
+            });
+            let face = Face { voxel, direction };
+            for attribute in attributes {
+                attribute.apply(&face, &mut corners);
+            }
+            builder.quad(corners, direction.reversed());
+        }
+    }
+    builder.finish()
+}
+
+/// Water surface from `VoxelStorage::surface_faces`. Water voxels with air above are lowered by
+/// `lowering`, so the surface sits below the ground around it. `filled` tells whether a voxel
+/// relative to the chunk holds water or ground. Only positions and normals are set.
+pub fn water(faces: &Faces, filled: impl Fn([i32; 3]) -> bool, lowering: f32) -> MeshData {
+    let mut builder = MeshBuilder::default();
+    for direction in Direction::ALL {
+        let normal = direction.normal().map(|n| n as f32);
+        for &[x, y, z] in direction.of(faces) {
+            let voxel = [x as i32, y as i32, z as i32];
+            let top = if filled([voxel[0], voxel[1] + 1, voxel[2]]) {
+                1.0
+            } else {
+                1.0 - lowering
+            };
+            let corners = direction.corners().map(|c| {
+                let mut position = [0, 1, 2].map(|i| (voxel[i] + c[i]) as f32);
+                if c[1] == 1 {
+                    position[1] = voxel[1] as f32 + top;
+                }
+                Vertex::new(position, normal)
+            });
+            builder.quad(corners, direction.reversed());
+        }
+    }
+    let mut mesh = builder.finish();
+    mesh.uvs.clear();
+    mesh.colors.clear();
+    mesh.tangents.clear();
+    mesh
+}
+
+/// ground of the world as occluder for the ambient occlusion of a chunk mesh, reaches into the
+/// neighbouring chunks
+pub fn ground_occluder(world: &VoxelWorld, coord: [i8; 2]) -> impl Fn([i32; 3]) -> bool + '_ {
+    let origin = [coord[0] as i32 * 64, coord[1] as i32 * 64];
+    move |p| world.is_ground([origin[0] + p[0], p[1], origin[1] + p[2]])
+}
+
+/// voxels holding water or ground, relative to the chunk, reaches into the neighbouring chunks
+pub fn filled(world: &VoxelWorld, coord: [i8; 2]) -> impl Fn([i32; 3]) -> bool + '_ {
+    let origin = [coord[0] as i32 * 64, coord[1] as i32 * 64];
+    move |p| {
+        let p = [origin[0] + p[0], p[1], origin[1] + p[2]];
+        world.is_ground(p) || world.is_water(p)
+    }
+}
+
+/// blocky ground of a chunk with planar uvs and ambient occlusion, in chunk local coordinates
+pub fn chunk_ground(world: &VoxelWorld, coord: [i8; 2]) -> MeshData {
+    let faces = world.ground[&coord].visible_faces();
+    let occlusion = AmbientOcclusion(ground_occluder(world, coord));
+    mesh(&faces, &[&PlanarUv, &occlusion])
+}
+
+/// water surface of a chunk bordering air, in chunk local coordinates
+pub fn chunk_water(world: &VoxelWorld, coord: [i8; 2], lowering: f32) -> MeshData {
+    let faces = world.water[&coord].surface_faces(&world.ground[&coord], filled(world, coord));
+    water(&faces, filled(world, coord), lowering)
+}
+
+/// first axis of the face as tangent, the sign makes the binormal point along the second axis
+fn tangent(direction: Direction) -> [f32; 4] {
+    let [u, v] = direction.axes();
+    let n = direction.normal();
+    let mut t = [0; 3];
+    t[u] = 1;
+    let binormal = [
+        n[1] * t[2] - n[2] * t[1],
+        n[2] * t[0] - n[0] * t[2],
+        n[0] * t[1] - n[1] * t[0],
+    ];
+    let sign = if binormal[v] > 0 { 1.0 } else { -1.0 };
+    [t[0] as f32, t[1] as f32, t[2] as f32, sign]
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::mesh_data::{triangle_normal, MeshData};
+    use crate::voxel_storage::{Faces, VoxelStorage};
+
+    use super::{mesh, water, AmbientOcclusion, Direction, PlanarUv};
+
+    fn normalize(v: [f32; 3]) -> [f32; 3] {
+        let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+        v.map(|c| c / length)
+    }
+
+    /// every edge is used once in each direction
+    fn assert_watertight(mesh: &MeshData) {
+        let mut edges = HashMap::new();
+        for triangle in mesh.triangles() {
+            for i in 0..3 {
+                let edge = (
+                    triangle[i].map(f32::to_bits),
+                    triangle[(i + 1) % 3].map(f32::to_bits),
+                );
+                *edges.entry(edge).or_insert(0) += 1;
+            }
+        }
+        for ((a, b), count) in edges.iter() {
+            assert_eq!(*count, 1);
+            assert_eq!(edges.get(&(*b, *a)), Some(&1));
+        }
+    }
+
+    #[test]
+    fn face_directions() {
+        let voxel = [3, 4, 5];
+        for direction in Direction::ALL {
+            let mut storage = VoxelStorage::empty();
+            storage.set(voxel);
+            let all = storage.visible_faces();
+            let mut faces = Faces::empty();
+            let single = match direction {
+                Direction::Top => &mut faces.top,
+                Direction::Bottom => &mut faces.bottom,
+                Direction::Left => &mut faces.left,
+                Direction::Right => &mut faces.right,
+                Direction::Back => &mut faces.back,
+                Direction::Front => &mut faces.front,
+            };
+            single.extend_from_slice(direction.of(&all));
+            assert_eq!(faces.total(), 1, "{direction:?}");
+
+            let mesh = mesh(&faces, &[&PlanarUv]);
+            let normal = direction.normal().map(|n| n as f32);
+            assert_eq!(mesh.positions.len(), 4);
+            assert!(mesh.normals.iter().all(|n| *n == normal), "{direction:?}");
+            // clockwise winding seen from outside
+            for triangle in mesh.triangles() {
+                assert_eq!(
+                    normalize(triangle_normal(triangle)),
+                    normal,
+                    "{direction:?}"
+                );
+            }
+            // the face lies on the side of the voxel it points to
+            let n = direction.normal();
+            let axis = n.iter().position(|c| *c != 0).unwrap();
+            let plane = (voxel[axis] as i32 + n[axis].max(0)) as f32;
+            assert!(
+                mesh.positions.iter().all(|p| p[axis] == plane),
+                "{direction:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn closed_shapes_are_watertight() {
+        let mut storage = VoxelStorage::empty();
+        // an L shape, a voxel touching it only along an edge and a voxel at the top of the world
+        for x in 10..14 {
+            storage.set([x, 0, 10]);
+        }
+        storage.set([10, 1, 10]);
+        storage.set([11, 2, 11]);
+        storage.set([30, 63, 30]);
+        let faces = storage.visible_faces();
+        let occluder =
+            |p: [i32; 3]| p.iter().all(|c| (0..64).contains(c)) && storage.get(p.map(|c| c as u8));
+        let mesh = mesh(&faces, &[&PlanarUv, &AmbientOcclusion(occluder)]);
+        assert_eq!(mesh.triangle_count(), faces.total() * 2);
+        assert_watertight(&mesh);
+    }
+
+    #[test]
+    fn top_most_layer() {
+        let mut storage = VoxelStorage::empty();
+        storage.set([7, 63, 7]);
+        let faces = storage.visible_faces();
+        assert_eq!(faces.top, vec![[7, 63, 7]]);
+        let mesh = mesh(&faces, &[]);
+        let top: Vec<_> = mesh
+            .positions
+            .iter()
+            .zip(mesh.normals.iter())
+            .filter(|(_, n)| **n == [0.0, 1.0, 0.0])
+            .map(|(p, _)| p[1])
+            .collect();
+        assert_eq!(top, vec![64.0; 4]);
+    }
+
+    #[test]
+    fn lowered_water_surface() {
+        let mut water_storage = VoxelStorage::empty();
+        water_storage.set([1, 0, 1]);
+        water_storage.set([1, 1, 1]);
+        let ground = VoxelStorage::empty();
+        let filled = |p: [i32; 3]| {
+            p.iter().all(|c| (0..64).contains(c)) && water_storage.get(p.map(|c| c as u8))
+        };
+        let faces = water_storage.surface_faces(&ground, filled);
+        let mesh = water(&faces, filled, 0.25);
+        assert!(mesh.uvs.is_empty() && mesh.colors.is_empty());
+        let max = mesh.positions.iter().map(|p| p[1]).fold(0.0, f32::max);
+        assert_eq!(max, 1.75);
+        // no face between the two water voxels, so the column stays closed apart from the floor
+        assert_eq!(mesh.triangle_count(), (1 + 4 * 2) * 2);
+    }
+
+    #[test]
+    fn floor_shares_vertices() {
+        let mut storage = VoxelStorage::empty();
+        for x in 0..64 {
+            for z in 0..64 {
+                storage.set([x, 0, z]);
+            }
+        }
+        let faces = storage.visible_faces();
+        let occluder =
+            |p: [i32; 3]| p.iter().all(|c| (0..64).contains(c)) && storage.get(p.map(|c| c as u8));
+        let mesh = mesh(&faces, &[&PlanarUv, &AmbientOcclusion(occluder)]);
+        assert_eq!(mesh.triangle_count(), faces.total() * 2);
+        // top and bottom share their grid of vertices, the four sides a row of 65 x 2 each
+        assert_eq!(mesh.positions.len(), 65 * 65 * 2 + 4 * 65 * 2);
+        assert!(mesh.colors.iter().all(|c| *c == [1.0; 4]));
+    }
+}
@@ -0,0 +1,91 @@
+use godot::engine::INode;
+use godot::engine::RigidBody3D;
+use godot::prelude::*;
+
+use crate::find_world;
+
+/// Applies buoyancy, drag and the push of flowing water to the parent `RigidBody3D`.
+/// The water is sampled from the first node in the "world" group.
+#[derive(GodotClass)]
+#[class(base=Node)]
+struct Buoyancy {
+    base: Base<Node>,
+    /// upwards force on the whole body once all probes are fully submerged, as a multiple of the
+    /// body's weight, each probe contributes an equal share
+    #[export]
+    buoyancy: f32,
+    /// depth at which a probe is considered fully submerged
+    #[export]
+    full_depth: f32,
+    #[export]
+    linear_drag: f32,
+    #[export]
+    angular_drag: f32,
+    /// force in direction of the water flow at full submersion, per cell per step of flow
+    #[export]
+    flow_strength: f32,
+    #[export]
+    gravity: f32,
+    /// positions relative to the body at which the water is sampled, the body origin if empty
+    #[export]
+    probes: PackedVector3Array,
+}
+
+#[godot_api]
+impl INode for Buoyancy {
+    fn init(base: Base<Node>) -> Self {
+        Buoyancy {
+            base,
+            buoyancy: 1.5,
+            full_depth: 1.0,
+            linear_drag: 1.0,
+            angular_drag: 1.0,
+            flow_strength: 2.0,
+            gravity: 9.8,
+            probes: PackedVector3Array::new(),
+        }
+    }
+
+    fn physics_process(&mut self, _delta: f64) {
+        let Some(parent) = self.base().get_parent() else {
+            return;
+        };
+        let Ok(mut body) = parent.try_cast::<RigidBody3D>() else {
+            return;
+        };
+        let Some(world) = find_world(self.base().get_tree()) else {
+            return;
+        };
+
+        let mut probes: Vec<Vector3> = self.probes.as_slice().to_vec();
+        if probes.is_empty() {
+            probes.push(Vector3::ZERO);
+        }
+        let weight = body.get_mass() * self.gravity;
+        let transform = body.get_global_transform();
+        let mut submerged = 0.0;
+        let mut flow = Vector3::ZERO;
+        for probe in probes.iter() {
+            let position = transform * *probe;
+            let w = world.bind();
+            let depth = w.water_depth(position);
+            if depth <= 0.0 {
+                continue;
+            }
+            let fraction = (depth / self.full_depth.max(0.001)).min(1.0) / probes.len() as f32;
+            body.apply_force_ex(Vector3::UP * weight * self.buoyancy * fraction)
+                .position(position - transform.origin)
+                .done();
+            submerged += fraction;
+            flow += w.water_flow_direction(position) * fraction;
+        }
+        if submerged > 0.0 {
+            let mass = body.get_mass();
+            let velocity = body.get_linear_velocity();
+            let angular_velocity = body.get_angular_velocity();
+            body.apply_central_force(-velocity * self.linear_drag * submerged * mass);
+            body.apply_torque(-angular_velocity * self.angular_drag * submerged * mass);
+            body.apply_central_force(flow * self.flow_strength * mass);
+        }
+    }
+}
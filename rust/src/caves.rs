@@ -0,0 +1,137 @@
+use std::ops::Range;
+
+use noise::{NoiseFn, OpenSimplex};
+
+use crate::voxel_storage::ChunkStorage;
+
+pub struct CaveSettings {
+    pub seed: u32,
+    /// Cheese caves: large open chambers wherever the 3D noise exceeds this threshold. The noise
+    /// stays within about -1..1, so 1 or more disables them.
+    pub cheese_threshold: f64,
+    pub cheese_frequency: f64,
+    /// Worm caves: tunnels along the lines where two noise fields are both within this distance
+    /// of zero, 0 disables them.
+    pub worm_radius: f64,
+    pub worm_frequency: f64,
+    /// heights in which cells are carved
+    pub depth: Range<u8>,
+    /// ground cells kept between a cave and the surface of its pillar
+    pub min_cover: u8,
+}
+
+impl Default for CaveSettings {
+    fn default() -> Self {
+        CaveSettings {
+            seed: 0,
+            cheese_threshold: 0.6,
+            cheese_frequency: 0.03,
+            worm_radius: 0.06,
+            worm_frequency: 0.02,
+            depth: 1..56,
+            min_cover: 4,
+        }
+    }
+}
+
+/// Removes ground where the cave noise is carved, after the terrain height is set.
+/// The noise is sampled in world space, so caves continue across chunk borders.
+pub fn carve_caves(ground: &mut ChunkStorage, settings: &CaveSettings) {
+    let cheese = OpenSimplex::new(settings.seed);
+    let worm_a = OpenSimplex::new(settings.seed.wrapping_add(1));
+    let worm_b = OpenSimplex::new(settings.seed.wrapping_add(2));
+    for (coord, chunk) in ground.iter_mut() {
+        for lz in 0..64 {
+            for lx in 0..64 {
+                let pillar = chunk.get_pillar([lx, lz]);
+                let surface = 64 - pillar.leading_zeros() as u8;
+                let top = surface
+                    .saturating_sub(settings.min_cover)
+                    .min(settings.depth.end);
+                let x = coord[0] as f64 * 64.0 + lx as f64;
+                let z = coord[1] as f64 * 64.0 + lz as f64;
+                let mut carved = 0u64;
+                for y in settings.depth.start..top {
+                    if pillar & (1 << y) == 0 {
+                        continue;
+                    }
+                    let cheese_point = [x, y as f64, z].map(|v| v * settings.cheese_frequency);
+                    let worm_point = [x, y as f64, z].map(|v| v * settings.worm_frequency);
+                    let is_cheese = settings.cheese_threshold < 1.0
+                        && cheese.get(cheese_point) > settings.cheese_threshold;
+                    let is_worm = settings.worm_radius > 0.0
+                        && worm_a.get(worm_point).abs() < settings.worm_radius
+                        && worm_b.get(worm_point).abs() < settings.worm_radius;
+                    if is_cheese || is_worm {
+                        carved |= 1 << y;
+                    }
+                }
+                chunk.set_pillar([lx, lz], pillar & !carved);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::voxel_storage::{ChunkStorage, VoxelStorage};
+
+    use super::{carve_caves, CaveSettings};
+
+    fn solid(xs: std::ops::Range<i8>) -> ChunkStorage {
+        let mut ground = ChunkStorage::new();
+        for x in xs {
+            let mut chunk = VoxelStorage::empty();
+            for lx in 0..64 {
+                for lz in 0..64 {
+                    chunk.set_pillar([lx, lz], u64::MAX >> 4);
+                }
+            }
+            ground.insert([x, 0], chunk);
+        }
+        ground
+    }
+
+    #[test]
+    fn carves_within_depth_below_cover() {
+        let mut ground = solid(0..2);
+        let settings = CaveSettings {
+            depth: 8..64,
+            min_cover: 6,
+            ..Default::default()
+        };
+        carve_caves(&mut ground, &settings);
+        let carved: u64 = ground.values().map(|c| 64 * 64 * 60 - c.count()).sum();
+        assert!(carved > 0);
+        for chunk in ground.values() {
+            for pillar in chunk.raw.iter() {
+                // below the depth range and the cover below the surface at 60 are kept
+                assert_eq!(pillar & 0xff, 0xff);
+                assert_eq!(pillar >> 54, 0b111111);
+            }
+        }
+    }
+
+    #[test]
+    fn disabled_caves_keep_the_ground() {
+        let mut ground = solid(0..1);
+        let settings = CaveSettings {
+            cheese_threshold: 1.0,
+            worm_radius: 0.0,
+            ..Default::default()
+        };
+        carve_caves(&mut ground, &settings);
+        assert_eq!(ground[&[0, 0]].count(), 64 * 64 * 60);
+    }
+
+    #[test]
+    fn caves_use_world_positions() {
+        // carving a chunk alone or next to its neighbour gives the same result
+        let mut pair = solid(0..2);
+        let mut single = solid(1..2);
+        carve_caves(&mut pair, &CaveSettings::default());
+        carve_caves(&mut single, &CaveSettings::default());
+        assert_eq!(pair[&[1, 0]].raw, single[&[1, 0]].raw);
+        assert_ne!(pair[&[0, 0]].raw, pair[&[1, 0]].raw);
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+
+use crate::voxel_storage::VoxelWorld;
+
+/// set of cells connected through faces, possibly spanning several chunks
+pub struct Component {
+    pub volume: u64,
+    pub min_y: u8,
+    pub max_y: u8,
+    /// contains cells at y = 63
+    pub touches_top: bool,
+    /// contains cells at y = 0
+    pub touches_bottom: bool,
+    /// contains cells at the outer border of the generated chunks
+    pub touches_sides: bool,
+}
+
+impl Component {
+    /// Nothing can flow in or out of the component without passing through the world. The floor
+    /// of the world is solid, so touching the bottom keeps the component sealed.
+    pub fn is_sealed(&self) -> bool {
+        !self.touches_top && !self.touches_sides
+    }
+}
+
+/// Labelled connected components of a selection of cells of a `VoxelWorld`.
+/// Works on vertical runs of set bits in the pillars, runs of neighbouring pillars are connected
+/// if their bit masks overlap.
+pub struct Components {
+    pub components: Vec<Component>,
+    /// per chunk and pillar (x + z * 64) the runs as (bit mask, component index), ordered by height
+    runs: HashMap<[i8; 2], Vec<Vec<(u64, u32)>>>,
+}
+
+impl Components {
+    /// component index of the cell at the given position in world space
+    pub fn label_at(&self, world: &VoxelWorld, p: [i32; 3]) -> Option<u32> {
+        let (chunk, local) = world.locate(p)?;
+        let pillar = &self.runs.get(&chunk)?[local[0] as usize + local[2] as usize * 64];
+        pillar
+            .iter()
+            .find(|(mask, _)| (mask >> local[1]) & 1 == 1)
+            .map(|(_, label)| *label)
+    }
+
+    pub fn component_at(&self, world: &VoxelWorld, p: [i32; 3]) -> Option<&Component> {
+        self.label_at(world, p)
+            .map(|label| &self.components[label as usize])
+    }
+}
+
+/// connected bodies of water
+pub fn label_water(world: &VoxelWorld) -> Components {
+    label(world, |chunk| world.water[&chunk].raw.clone())
+}
+
+/// connected cells not occupied by ground, water counts as empty
+pub fn label_air(world: &VoxelWorld) -> Components {
+    label(world, |chunk| {
+        world.ground[&chunk].raw.iter().map(|p| !p).collect()
+    })
+}
+
+/// `select` returns the selected cells of a chunk, one u64 per pillar in the layout of `VoxelStorage::raw`
+pub fn label(world: &VoxelWorld, select: impl Fn([i8; 2]) -> Vec<u64>) -> Components {
+    let mut union = UnionFind::default();
+    let mut runs: HashMap<[i8; 2], Vec<Vec<(u64, u32)>>> = HashMap::new();
+    for chunk in world.ground.keys() {
+        let pillars = select(*chunk);
+        let chunk_runs = pillars
+            .iter()
+            .map(|&pillar| {
+                split_runs(pillar)
+                    .map(|mask| (mask, union.insert()))
+                    .collect()
+            })
+            .collect();
+        runs.insert(*chunk, chunk_runs);
+    }
+
+    for (chunk, chunk_runs) in runs.iter() {
+        for z in 0..64usize {
+            for x in 0..64usize {
+                let current = &chunk_runs[x + z * 64];
+                if current.is_empty() {
+                    continue;
+                }
+                // connecting to -x and -z covers every pair of neighbouring pillars once
+                let left = if x > 0 {
+                    Some(&chunk_runs[x - 1 + z * 64])
+                } else {
+                    chunk[0]
+                        .checked_sub(1)
+                        .and_then(|x| runs.get(&[x, chunk[1]]))
+                        .map(|r| &r[63 + z * 64])
+                };
+                let front = if z > 0 {
+                    Some(&chunk_runs[x + (z - 1) * 64])
+                } else {
+                    chunk[1]
+                        .checked_sub(1)
+                        .and_then(|z| runs.get(&[chunk[0], z]))
+                        .map(|r| &r[x + 63 * 64])
+                };
+                for other in [left, front].into_iter().flatten() {
+                    for (mask, id) in current.iter() {
+                        for (other_mask, other_id) in other.iter() {
+                            if mask & other_mask != 0 {
+                                union.join(*id, *other_id);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut labels: HashMap<u32, u32> = HashMap::new();
+    let mut components: Vec<Component> = Vec::new();
+    for (chunk, chunk_runs) in runs.iter_mut() {
+        for (pillar, pillar_runs) in chunk_runs.iter_mut().enumerate() {
+            let x = pillar % 64;
+            let z = pillar / 64;
+            let side = (x == 0 && chunk[0] == world.xs.start)
+                || (x == 63 && chunk[0] == world.xs.end - 1)
+                || (z == 0 && chunk[1] == world.zs.start)
+                || (z == 63 && chunk[1] == world.zs.end - 1);
+            for (mask, id) in pillar_runs.iter_mut() {
+                let root = union.find(*id);
+                let label = *labels.entry(root).or_insert_with(|| {
+                    components.push(Component {
+                        volume: 0,
+                        min_y: 63,
+                        max_y: 0,
+                        touches_top: false,
+                        touches_bottom: false,
+                        touches_sides: false,
+                    });
+                    components.len() as u32 - 1
+                });
+                let component = &mut components[label as usize];
+                let bottom = mask.trailing_zeros() as u8;
+                let top = 63 - mask.leading_zeros() as u8;
+                component.volume += mask.count_ones() as u64;
+                component.min_y = component.min_y.min(bottom);
+                component.max_y = component.max_y.max(top);
+                component.touches_bottom |= bottom == 0;
+                component.touches_top |= top == 63;
+                component.touches_sides |= side;
+                *id = label;
+            }
+        }
+    }
+    Components { components, runs }
+}
+
+/// splits a pillar into the masks of its runs of consecutive set bits, bottom to top
+fn split_runs(mut pillar: u64) -> impl Iterator<Item = u64> {
+    std::iter::from_fn(move || {
+        if pillar == 0 {
+            return None;
+        }
+        let start = pillar.trailing_zeros();
+        let length = (pillar >> start).trailing_ones();
+        let run = (u64::MAX >> (64 - length)) << start;
+        pillar &= !run;
+        Some(run)
+    })
+}
+
+#[derive(Default)]
+struct UnionFind {
+    parent: Vec<u32>,
+}
+
+impl UnionFind {
+    fn insert(&mut self) -> u32 {
+        self.parent.push(self.parent.len() as u32);
+        self.parent.len() as u32 - 1
+    }
+
+    fn find(&mut self, mut id: u32) -> u32 {
+        while self.parent[id as usize] != id {
+            let grand_parent = self.parent[self.parent[id as usize] as usize];
+            self.parent[id as usize] = grand_parent;
+            id = grand_parent;
+        }
+        id
+    }
+
+    fn join(&mut self, a: u32, b: u32) {
+        let a = self.find(a);
+        let b = self.find(b);
+        if a != b {
+            self.parent[a.max(b) as usize] = a.min(b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::voxel_storage::VoxelWorld;
+
+    use super::{label_air, label_water, split_runs};
+
+    #[test]
+    fn runs_of_pillar() {
+        let runs: Vec<u64> = split_runs(0b111 | 0b1 << 59 | 0b11 << 62).collect();
+        assert_eq!(runs, vec![0b111, 0b1 << 59, 0b11 << 62]);
+        assert_eq!(split_runs(u64::MAX).collect::<Vec<_>>(), vec![u64::MAX]);
+        assert_eq!(split_runs(0).count(), 0);
+    }
+
+    #[test]
+    fn water_connected_across_chunks() {
+        let mut world = VoxelWorld::empty(0..2, 0..2);
+        // diagonal neighbours are not connected
+        world.water.get_mut(&[0, 0]).unwrap().set([63, 5, 63]);
+        world.water.get_mut(&[1, 1]).unwrap().set([0, 5, 0]);
+        // overlapping runs in neighbouring chunks are
+        world.water.get_mut(&[0, 1]).unwrap().set([63, 5, 10]);
+        world.water.get_mut(&[0, 1]).unwrap().set([63, 6, 10]);
+        world.water.get_mut(&[1, 1]).unwrap().set([0, 6, 10]);
+        world.water.get_mut(&[1, 1]).unwrap().set([0, 7, 10]);
+
+        let components = label_water(&world);
+        assert_eq!(components.components.len(), 3);
+        let joined = components.component_at(&world, [64, 7, 74]).unwrap();
+        assert_eq!(joined.volume, 4);
+        assert_eq!(joined.min_y, 5);
+        assert_eq!(joined.max_y, 7);
+        assert_ne!(
+            components.label_at(&world, [63, 5, 63]),
+            components.label_at(&world, [64, 5, 64])
+        );
+        assert_eq!(components.label_at(&world, [63, 4, 63]), None);
+    }
+
+    #[test]
+    fn sealed_cave() {
+        let mut world = VoxelWorld::empty(0..1, 0..1);
+        let ground = world.ground.get_mut(&[0, 0]).unwrap();
+        for x in 0..64 {
+            for z in 0..64 {
+                for y in 0..40 {
+                    ground.set([x, y, z]);
+                }
+            }
+        }
+        for x in 10..13 {
+            for y in 10..12 {
+                for z in 20..22 {
+                    ground.unset([x, y, z]);
+                }
+            }
+        }
+        let components = label_air(&world);
+        assert_eq!(components.components.len(), 2);
+        let cave = components.component_at(&world, [11, 10, 21]).unwrap();
+        assert_eq!(cave.volume, 12);
+        assert!(cave.is_sealed());
+        let sky = components.component_at(&world, [11, 50, 21]).unwrap();
+        assert!(!sky.is_sealed());
+        assert!(sky.touches_top && sky.touches_sides && !sky.touches_bottom);
+    }
+
+    #[test]
+    fn cave_on_the_floor_is_sealed() {
+        // the chunk at the lowest coordinate has no neighbour towards -x and -z
+        let mut world = VoxelWorld::empty(-128..-127, -128..-127);
+        let ground = world.ground.get_mut(&[-128, -128]).unwrap();
+        for x in 0..64 {
+            for z in 0..64 {
+                ground.set_pillar([x, z], u64::MAX >> 24);
+            }
+        }
+        for x in 10..13 {
+            for z in 20..22 {
+                ground.unset([x, 0, z]);
+            }
+        }
+        let components = label_air(&world);
+        let cave = components
+            .component_at(&world, [-128 * 64 + 11, 0, -128 * 64 + 21])
+            .unwrap();
+        assert!(cave.touches_bottom);
+        assert!(cave.is_sealed());
+    }
+}
@@ -0,0 +1,281 @@
+use std::ops::Range;
+
+use crate::voxel_storage::{low_bits, ChunkStorage};
+
+/// Droplet erosion: each droplet runs downhill over the surface heights, takes up ground where
+/// it speeds up and drops it where it slows down, carving valleys and leaving sediment fans.
+pub struct ErosionSettings {
+    pub seed: u32,
+    /// droplets per chunk
+    pub iterations: usize,
+    /// steps a droplet runs before it evaporates
+    pub lifetime: usize,
+    /// how much a droplet keeps its direction instead of following the slope, between 0 and 1
+    pub inertia: f32,
+    /// sediment a droplet can carry per unit of speed, water and height it falls
+    pub capacity: f32,
+    /// capacity on flat ground
+    pub min_capacity: f32,
+    /// fraction of the free capacity taken up per step
+    pub erode_speed: f32,
+    /// fraction of the excess sediment dropped per step
+    pub deposit_speed: f32,
+    /// fraction of the water lost per step
+    pub evaporation: f32,
+    pub gravity: f32,
+}
+
+impl Default for ErosionSettings {
+    fn default() -> Self {
+        ErosionSettings {
+            seed: 0,
+            iterations: 2048,
+            lifetime: 48,
+            inertia: 0.1,
+            capacity: 4.0,
+            min_capacity: 0.01,
+            erode_speed: 0.3,
+            deposit_speed: 0.3,
+            evaporation: 0.02,
+            gravity: 4.0,
+        }
+    }
+}
+
+/// Surface heights of all pillars in rows of `width` pillars along x, starting at the corner of
+/// the chunk (xs.start, zs.start).
+struct Heights {
+    width: usize,
+    depth: usize,
+    values: Vec<f32>,
+}
+
+impl Heights {
+    fn at(&self, x: usize, z: usize) -> f32 {
+        self.values[x + z * self.width]
+    }
+
+    /// bilinear height and gradient at a position inside of the map
+    fn sample(&self, p: [f32; 2]) -> (f32, [f32; 2]) {
+        let (x, z) = (p[0] as usize, p[1] as usize);
+        let (u, v) = (p[0] - x as f32, p[1] - z as f32);
+        let h00 = self.at(x, z);
+        let h10 = self.at(x + 1, z);
+        let h01 = self.at(x, z + 1);
+        let h11 = self.at(x + 1, z + 1);
+        let height =
+            h00 * (1.0 - u) * (1.0 - v) + h10 * u * (1.0 - v) + h01 * (1.0 - u) * v + h11 * u * v;
+        let gradient = [
+            (h10 - h00) * (1.0 - v) + (h11 - h01) * v,
+            (h01 - h00) * (1.0 - u) + (h11 - h10) * u,
+        ];
+        (height, gradient)
+    }
+
+    /// spreads `amount` over the four pillars around the position by their bilinear weights
+    fn add(&mut self, p: [f32; 2], amount: f32) {
+        let (x, z) = (p[0] as usize, p[1] as usize);
+        let (u, v) = (p[0] - x as f32, p[1] - z as f32);
+        let i = x + z * self.width;
+        self.values[i] += amount * (1.0 - u) * (1.0 - v);
+        self.values[i + 1] += amount * u * (1.0 - v);
+        self.values[i + self.width] += amount * (1.0 - u) * v;
+        self.values[i + self.width + 1] += amount * u * v;
+    }
+
+    fn contains(&self, p: [f32; 2]) -> bool {
+        p[0] >= 0.0
+            && p[1] >= 0.0
+            && p[0] < (self.width - 1) as f32
+            && p[1] < (self.depth - 1) as f32
+    }
+}
+
+/// Erodes the surface of the ground. Pillars are rebuilt solid from their new height, so this
+/// runs before any pass that hollows out the ground.
+pub fn erode(
+    xs: &Range<i8>,
+    zs: &Range<i8>,
+    ground: &mut ChunkStorage,
+    settings: &ErosionSettings,
+) {
+    let width = xs.len() * 64;
+    let depth = zs.len() * 64;
+    let index = |coord: &[i8; 2], lx: u8, lz: u8| {
+        (coord[0] as i32 - xs.start as i32) as usize * 64
+            + lx as usize
+            + ((coord[1] as i32 - zs.start as i32) as usize * 64 + lz as usize) * width
+    };
+    let mut heights = Heights {
+        width,
+        depth,
+        values: vec![0.0; width * depth],
+    };
+    for (coord, chunk) in ground.iter() {
+        for lz in 0..64 {
+            for lx in 0..64 {
+                let pillar = chunk.get_pillar([lx, lz]);
+                heights.values[index(coord, lx, lz)] = (64 - pillar.leading_zeros()) as f32;
+            }
+        }
+    }
+
+    let mut random = Random(settings.seed as u64);
+    let droplets = settings.iterations * xs.len() * zs.len();
+    for _ in 0..droplets {
+        let mut p = [
+            random.next() * (width - 1) as f32,
+            random.next() * (depth - 1) as f32,
+        ];
+        let mut direction = [0.0f32; 2];
+        let mut speed = 1.0f32;
+        let mut water = 1.0f32;
+        let mut sediment = 0.0f32;
+        for _ in 0..settings.lifetime {
+            let (height, gradient) = heights.sample(p);
+            direction = [0, 1]
+                .map(|a| direction[a] * settings.inertia - gradient[a] * (1.0 - settings.inertia));
+            let length = (direction[0] * direction[0] + direction[1] * direction[1]).sqrt();
+            if length < 1e-6 {
+                break;
+            }
+            direction = direction.map(|d| d / length);
+            let next = [p[0] + direction[0], p[1] + direction[1]];
+            if !heights.contains(next) {
+                break;
+            }
+            let delta = heights.sample(next).0 - height;
+            let capacity = (-delta * speed * water * settings.capacity).max(settings.min_capacity);
+            if delta > 0.0 || sediment > capacity {
+                // uphill the droplet fills the pit behind it, otherwise it drops the excess
+                let deposit = if delta > 0.0 {
+                    delta.min(sediment)
+                } else {
+                    (sediment - capacity) * settings.deposit_speed
+                };
+                sediment -= deposit;
+                heights.add(p, deposit);
+            } else {
+                // never takes more than the height difference, so no pits are dug
+                let taken = ((capacity - sediment) * settings.erode_speed).min(-delta);
+                sediment += taken;
+                heights.add(p, -taken);
+            }
+            speed = (speed * speed - delta * settings.gravity).max(0.0).sqrt();
+            water *= 1.0 - settings.evaporation;
+            p = next;
+        }
+    }
+
+    for (coord, chunk) in ground.iter_mut() {
+        for lz in 0..64 {
+            for lx in 0..64 {
+                let height = heights.values[index(coord, lx, lz)]
+                    .round()
+                    .clamp(1.0, 64.0);
+                chunk.set_pillar([lx, lz], low_bits(height as u32));
+            }
+        }
+    }
+}
+
+/// splitmix64 stream of numbers between 0 and 1
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> f32 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::voxel_storage::{low_bits, ChunkStorage, VoxelStorage};
+
+    use super::{erode, ErosionSettings};
+
+    /// a slope falling towards x = 0 onto a flat plain at x < 24
+    fn slope() -> ChunkStorage {
+        let mut ground = ChunkStorage::new();
+        for cx in 0..2 {
+            let mut chunk = VoxelStorage::empty();
+            for x in 0..64 {
+                for z in 0..64 {
+                    let wx = cx * 64 + x as i32;
+                    let height = 4 + (wx - 24).max(0) / 2;
+                    chunk.set_pillar([x, z], low_bits(height as u32));
+                }
+            }
+            ground.insert([cx as i8, 0], chunk);
+        }
+        ground
+    }
+
+    fn height(ground: &ChunkStorage, x: i32, z: u8) -> u32 {
+        let pillar = ground[&[(x / 64) as i8, 0]].get_pillar([(x % 64) as u8, z]);
+        64 - pillar.leading_zeros()
+    }
+
+    #[test]
+    fn valleys_and_fans() {
+        let before = slope();
+        let mut after = slope();
+        erode(&(0..2), &(0..1), &mut after, &ErosionSettings::default());
+        let mut eroded = 0;
+        let mut deposited = 0;
+        for x in 0..128 {
+            for z in 0..64 {
+                let (b, a) = (height(&before, x, z), height(&after, x, z));
+                if a < b && x > 24 {
+                    eroded += 1;
+                }
+                if a > b && x <= 30 {
+                    deposited += 1;
+                }
+                assert!((1..=64).contains(&a));
+            }
+        }
+        assert!(eroded > 0, "no valleys on the slope");
+        assert!(deposited > 0, "no sediment at the foot of the slope");
+    }
+
+    #[test]
+    fn chunks_further_apart_than_i8() {
+        let mut ground = ChunkStorage::new();
+        for x in -128..1 {
+            let mut chunk = VoxelStorage::empty();
+            for lx in 0..64 {
+                for lz in 0..64 {
+                    chunk.set_pillar([lx, lz], low_bits(lx as u32 / 4 + 1));
+                }
+            }
+            ground.insert([x, 0], chunk);
+        }
+        let settings = ErosionSettings {
+            iterations: 8,
+            ..Default::default()
+        };
+        erode(&(-128..1), &(0..1), &mut ground, &settings);
+        assert!(ground.values().all(|c| c.raw.iter().all(|p| p & 1 == 1)));
+    }
+
+    #[test]
+    fn erosion_is_deterministic() {
+        let mut a = slope();
+        let mut b = slope();
+        let settings = ErosionSettings {
+            iterations: 256,
+            ..Default::default()
+        };
+        erode(&(0..2), &(0..1), &mut a, &settings);
+        erode(&(0..2), &(0..1), &mut b, &settings);
+        for (coord, chunk) in a.iter() {
+            assert_eq!(chunk.raw, b[coord].raw);
+        }
+    }
+}
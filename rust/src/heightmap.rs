@@ -0,0 +1,282 @@
+use std::ops::Range;
+
+use crate::voxel_storage::{low_bits, VoxelStorage, VoxelWorld};
+
+/// grayscale image with samples between 0 and 1, row by row
+pub struct Heightmap {
+    pub width: usize,
+    pub height: usize,
+    pub samples: Vec<f32>,
+}
+
+impl Heightmap {
+    /// Detects the format by its signature: PNG, binary or ascii PGM, otherwise a square image
+    /// of little endian 16 bit samples without header. None if the input has no signature and
+    /// its length is not exactly `2 * side * side`.
+    pub fn decode(bytes: &[u8]) -> Option<Heightmap> {
+        if bytes.starts_with(b"\x89PNG") {
+            Heightmap::from_png(bytes)
+        } else if bytes.starts_with(b"P5") || bytes.starts_with(b"P2") {
+            Heightmap::from_pgm(bytes)
+        } else {
+            let side = ((bytes.len() / 2) as f64).sqrt().round() as usize;
+            if side.checked_mul(side)?.checked_mul(2)? != bytes.len() {
+                return None;
+            }
+            Heightmap::from_raw16(bytes, side, side)
+        }
+    }
+
+    /// 8 or 16 bit PNG, colors are averaged and alpha is ignored
+    pub fn from_png(bytes: &[u8]) -> Option<Heightmap> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info().ok()?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).ok()?;
+        let (color, depth) = reader.output_color_type();
+        let channels = color.samples();
+        let colors = match color {
+            png::ColorType::Rgb | png::ColorType::Rgba => 3,
+            _ => 1,
+        };
+        let (bytes_per_sample, max) = match depth {
+            png::BitDepth::Sixteen => (2, u16::MAX as f32),
+            _ => (1, u8::MAX as f32),
+        };
+        let pixel = channels * bytes_per_sample;
+        let mut samples = Vec::with_capacity(info.width as usize * info.height as usize);
+        for row in buffer.chunks(info.line_size).take(info.height as usize) {
+            for p in row.chunks_exact(pixel).take(info.width as usize) {
+                let sum: f32 = (0..colors)
+                    .map(|c| {
+                        let s = &p[c * bytes_per_sample..(c + 1) * bytes_per_sample];
+                        match s {
+                            [high, low] => u16::from_be_bytes([*high, *low]) as f32,
+                            [value] => *value as f32,
+                            _ => 0.0,
+                        }
+                    })
+                    .sum();
+                samples.push(sum / colors as f32 / max);
+            }
+        }
+        Some(Heightmap {
+            width: info.width as usize,
+            height: info.height as usize,
+            samples,
+        })
+    }
+
+    /// binary (P5) or ascii (P2) PGM with 8 or 16 bit samples
+    pub fn from_pgm(bytes: &[u8]) -> Option<Heightmap> {
+        let binary = bytes.starts_with(b"P5");
+        let mut at = 2;
+        // width, height and the maximum value, separated by whitespace and comments
+        let mut header = [0usize; 3];
+        for value in header.iter_mut() {
+            loop {
+                match bytes.get(at)? {
+                    b'#' => {
+                        while *bytes.get(at)? != b'\n' {
+                            at += 1;
+                        }
+                    }
+                    c if c.is_ascii_whitespace() => at += 1,
+                    _ => break,
+                }
+            }
+            let start = at;
+            while bytes.get(at).is_some_and(|c| c.is_ascii_digit()) {
+                at += 1;
+            }
+            *value = std::str::from_utf8(&bytes[start..at]).ok()?.parse().ok()?;
+        }
+        let [width, height, max] = header;
+        if width == 0 || height == 0 || max == 0 || max > u16::MAX as usize {
+            return None;
+        }
+        let pixels = width.checked_mul(height)?;
+        let values: Vec<usize> = if binary {
+            // a single whitespace separates the header from the samples
+            let data = bytes.get(at + 1..)?;
+            if max < 256 {
+                data.iter().map(|v| *v as usize).collect()
+            } else {
+                data.chunks_exact(2)
+                    .map(|v| u16::from_be_bytes([v[0], v[1]]) as usize)
+                    .collect()
+            }
+        } else {
+            std::str::from_utf8(&bytes[at..])
+                .ok()?
+                .split_ascii_whitespace()
+                .map(|v| v.parse().ok())
+                .collect::<Option<_>>()?
+        };
+        if values.len() < pixels {
+            return None;
+        }
+        Some(Heightmap {
+            width,
+            height,
+            samples: values[..pixels]
+                .iter()
+                .map(|v| *v as f32 / max as f32)
+                .collect(),
+        })
+    }
+
+    /// little endian 16 bit samples without header
+    pub fn from_raw16(bytes: &[u8], width: usize, height: usize) -> Option<Heightmap> {
+        let pixels = width.checked_mul(height)?;
+        if pixels == 0 || bytes.len() / 2 < pixels {
+            return None;
+        }
+        Some(Heightmap {
+            width,
+            height,
+            samples: bytes
+                .chunks_exact(2)
+                .take(pixels)
+                .map(|v| u16::from_le_bytes([v[0], v[1]]) as f32 / u16::MAX as f32)
+                .collect(),
+        })
+    }
+
+    /// sample at the pixel, positions outside of the image are clamped to its border
+    pub fn sample(&self, x: i32, z: i32) -> f32 {
+        let x = x.clamp(0, self.width as i32 - 1) as usize;
+        let z = z.clamp(0, self.height as i32 - 1) as usize;
+        self.samples[x + z * self.width]
+    }
+}
+
+pub struct HeightmapSettings {
+    /// ground height in voxels of a white pixel, on top of `offset`
+    pub scale: f32,
+    pub offset: f32,
+    /// water fills pillars whose water mask sample is above 0.5 up to this height
+    pub water_level: u8,
+}
+
+/// World with one pillar per pixel, the top left pixel lies at the corner of the chunk
+/// (xs.start, zs.start). Ground reaches up to `sample * scale + offset`. None if the water mask
+/// is not of the same size as the heightmap.
+pub fn world_from_heightmap(
+    xs: Range<i8>,
+    zs: Range<i8>,
+    heightmap: &Heightmap,
+    water_mask: Option<&Heightmap>,
+    settings: &HeightmapSettings,
+) -> Option<VoxelWorld> {
+    if water_mask.is_some_and(|m| (m.width, m.height) != (heightmap.width, heightmap.height)) {
+        return None;
+    }
+    let mut world = VoxelWorld::empty(xs.clone(), zs.clone());
+    let origin = [xs.start as i32 * 64, zs.start as i32 * 64];
+    for cx in xs {
+        for cz in zs.clone() {
+            let mut ground = VoxelStorage::empty();
+            let mut water = VoxelStorage::empty();
+            for lx in 0..64u8 {
+                for lz in 0..64u8 {
+                    let px = cx as i32 * 64 + lx as i32 - origin[0];
+                    let pz = cz as i32 * 64 + lz as i32 - origin[1];
+                    let height = heightmap.sample(px, pz) * settings.scale + settings.offset;
+                    let filled = (0..64).filter(|y| (*y as f32) < height).count() as u32;
+                    let pillar = low_bits(filled);
+                    ground.set_pillar([lx, lz], pillar);
+                    if water_mask.is_some_and(|m| m.sample(px, pz) > 0.5) {
+                        let level = low_bits(settings.water_level.min(64) as u32);
+                        water.set_pillar([lx, lz], level & !pillar);
+                    }
+                }
+            }
+            world.ground.insert([cx, cz], ground);
+            world.water.insert([cx, cz], water);
+        }
+    }
+    Some(world)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{world_from_heightmap, Heightmap, HeightmapSettings};
+
+    fn png(width: u32, height: u32, depth: png::BitDepth, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(depth);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        writer.finish().unwrap();
+        bytes
+    }
+
+    #[test]
+    fn decode_formats() {
+        let eight = Heightmap::decode(&png(2, 1, png::BitDepth::Eight, &[0, 255])).unwrap();
+        assert_eq!(eight.samples, vec![0.0, 1.0]);
+        let sixteen =
+            Heightmap::decode(&png(1, 2, png::BitDepth::Sixteen, &[0xff, 0xff, 0, 0])).unwrap();
+        assert_eq!((sixteen.width, sixteen.height), (1, 2));
+        assert_eq!(sixteen.samples, vec![1.0, 0.0]);
+
+        let ascii = Heightmap::decode(b"P2\n# comment\n2 2\n4\n0 1\n2 4\n").unwrap();
+        assert_eq!(ascii.samples, vec![0.0, 0.25, 0.5, 1.0]);
+        let mut binary = b"P5 3 1 255\n".to_vec();
+        binary.extend_from_slice(&[0, 51, 255]);
+        let binary = Heightmap::decode(&binary).unwrap();
+        assert_eq!(binary.samples, vec![0.0, 0.2, 1.0]);
+
+        let raw = Heightmap::decode(&[0, 0, 0xff, 0xff, 0, 0, 0, 0x80]).unwrap();
+        assert_eq!((raw.width, raw.height), (2, 2));
+        assert_eq!(raw.samples[1], 1.0);
+        // neither a known signature nor a square of 16 bit samples
+        assert!(Heightmap::decode(&[0xff, 0xd8, 0xff, 0xe0, 0, 0x10, 0x4a, 0x46, 0x49]).is_none());
+        assert!(Heightmap::decode(&[0; 10]).is_none());
+        assert!(Heightmap::decode(b"P5 10 10 255\n").is_none());
+        assert!(Heightmap::decode(b"P5 0 0 255\n").is_none());
+        assert!(Heightmap::decode(b"P2 4294967296 4294967296 255\n0\n").is_none());
+    }
+
+    #[test]
+    fn ground_and_water_from_images() {
+        // a ramp along x, water only in pixel 0
+        let heightmap = Heightmap {
+            width: 128,
+            height: 1,
+            samples: (0..128).map(|x| x as f32 / 127.0).collect(),
+        };
+        let mask = Heightmap {
+            width: 128,
+            height: 1,
+            samples: (0..128).map(|x| if x == 0 { 1.0 } else { 0.0 }).collect(),
+        };
+        let settings = HeightmapSettings {
+            scale: 40.0,
+            offset: 2.0,
+            water_level: 10,
+        };
+        let world = world_from_heightmap(-1..1, 0..1, &heightmap, Some(&mask), &settings).unwrap();
+        // pixel 0 is at the corner of chunk -1, rows past the image repeat the last one
+        assert!(world.is_ground([-64, 1, 40]));
+        assert!(!world.is_ground([-64, 2, 40]));
+        assert!(world.is_ground([63, 41, 0]));
+        assert!(!world.is_ground([63, 42, 0]));
+        assert!(world.is_water([-64, 9, 0]));
+        assert!(!world.is_water([-64, 10, 0]));
+        assert!(!world.is_water([-63, 5, 0]));
+
+        let small_mask = Heightmap {
+            width: 2,
+            height: 1,
+            samples: vec![1.0, 0.0],
+        };
+        assert!(
+            world_from_heightmap(-1..1, 0..1, &heightmap, Some(&small_mask), &settings).is_none()
+        );
+    }
+}
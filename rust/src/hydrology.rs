@@ -0,0 +1,321 @@
+use std::{cmp::Reverse, collections::BinaryHeap, ops::Range};
+
+use crate::voxel_storage::{low_bits, ChunkStorage, VoxelStorage};
+
+pub struct RiverSettings {
+    pub seed: u32,
+    /// number of rivers, each starts at a spring on a pillar at least `spring_height` high
+    pub springs: usize,
+    pub spring_height: u8,
+    /// cells the river bed is carved below the terrain
+    pub depth: u8,
+    /// pillars around the course of the river that are carved as well
+    pub radius: u8,
+}
+
+impl Default for RiverSettings {
+    fn default() -> Self {
+        RiverSettings {
+            seed: 0,
+            springs: 8,
+            spring_height: 40,
+            depth: 2,
+            radius: 1,
+        }
+    }
+}
+
+/// Surface heights of all pillars of the world in rows of `width` pillars along x, starting at
+/// the corner of the chunk (xs.start, zs.start).
+struct Terrain {
+    width: usize,
+    depth: usize,
+    heights: Vec<u8>,
+}
+
+/// Result of flooding the terrain from the world border: `levels` is the height up to which
+/// water stays in each pillar and `downstream` the neighbour water leaves the pillar through,
+/// None at the border. Following `downstream` never rises and ends at the border.
+struct Flood {
+    levels: Vec<u8>,
+    downstream: Vec<Option<usize>>,
+}
+
+impl Terrain {
+    fn read(xs: &Range<i8>, zs: &Range<i8>, ground: &ChunkStorage) -> Terrain {
+        let width = xs.len() * 64;
+        let depth = zs.len() * 64;
+        let mut heights = vec![0; width * depth];
+        for (coord, chunk) in ground.iter() {
+            for lz in 0..64 {
+                for lx in 0..64 {
+                    let pillar = chunk.get_pillar([lx, lz]);
+                    let i = Terrain::index(xs, zs, *coord, [lx, lz], width);
+                    heights[i] = 64 - pillar.leading_zeros() as u8;
+                }
+            }
+        }
+        Terrain {
+            width,
+            depth,
+            heights,
+        }
+    }
+
+    fn index(
+        xs: &Range<i8>,
+        zs: &Range<i8>,
+        coord: [i8; 2],
+        local: [u8; 2],
+        width: usize,
+    ) -> usize {
+        let x = (coord[0] as i32 - xs.start as i32) as usize * 64 + local[0] as usize;
+        let z = (coord[1] as i32 - zs.start as i32) as usize * 64 + local[1] as usize;
+        x + z * width
+    }
+
+    fn neighbours(&self, i: usize) -> impl Iterator<Item = usize> + '_ {
+        let (x, z) = (i % self.width, i / self.width);
+        [
+            (x > 0).then(|| i - 1),
+            (x + 1 < self.width).then(|| i + 1),
+            (z > 0).then(|| i - self.width),
+            (z + 1 < self.depth).then(|| i + self.width),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    /// pillars within `radius` of the pillar, including itself
+    fn around(&self, i: usize, radius: u8) -> Vec<usize> {
+        let r = radius as i64;
+        let (x, z) = ((i % self.width) as i64, (i / self.width) as i64);
+        let mut pillars = Vec::new();
+        for dz in -r..=r {
+            for dx in -r..=r {
+                let (nx, nz) = (x + dx, z + dz);
+                if dx * dx + dz * dz <= r * r
+                    && (0..self.width as i64).contains(&nx)
+                    && (0..self.depth as i64).contains(&nz)
+                {
+                    pillars.push(nx as usize + nz as usize * self.width);
+                }
+            }
+        }
+        pillars
+    }
+
+    /// Priority flood: pillars are visited from the lowest level upwards starting at the border,
+    /// a pillar below the level it is reached with lies in a depression and holds a lake.
+    fn flood(&self) -> Flood {
+        let mut levels = self.heights.clone();
+        let mut downstream = vec![None; levels.len()];
+        let mut visited = vec![false; levels.len()];
+        let mut queue = BinaryHeap::new();
+        for i in 0..levels.len() {
+            let (x, z) = (i % self.width, i / self.width);
+            if x == 0 || z == 0 || x + 1 == self.width || z + 1 == self.depth {
+                visited[i] = true;
+                queue.push(Reverse((levels[i], i)));
+            }
+        }
+        while let Some(Reverse((level, i))) = queue.pop() {
+            for n in self.neighbours(i) {
+                if !visited[n] {
+                    visited[n] = true;
+                    levels[n] = levels[n].max(level);
+                    downstream[n] = Some(i);
+                    queue.push(Reverse((levels[n], n)));
+                }
+            }
+        }
+        Flood { levels, downstream }
+    }
+}
+
+/// Carves river beds from springs down to the world border or into a lake and fills lakes in
+/// the depressions of the terrain up to the height where they would spill over. Returns the
+/// water of the rivers and lakes, rivers are one cell deep. Lakes are filled up to their rim
+/// and rivers run downhill, so `simulate_water` starts close to equilibrium.
+pub fn rivers_and_lakes(
+    xs: &Range<i8>,
+    zs: &Range<i8>,
+    ground: &mut ChunkStorage,
+    settings: &RiverSettings,
+) -> ChunkStorage {
+    let mut terrain = Terrain::read(xs, zs, ground);
+    let flood = terrain.flood();
+
+    let mut springs: Vec<usize> = (0..terrain.heights.len())
+        .filter(|i| terrain.heights[*i] >= settings.spring_height)
+        .collect();
+    springs.sort_by_key(|i| hash(*i as u64 ^ (settings.seed as u64) << 32));
+    springs.truncate(settings.springs);
+
+    let lake: Vec<bool> = flood
+        .levels
+        .iter()
+        .zip(terrain.heights.iter())
+        .map(|(level, height)| level > height)
+        .collect();
+    let mut river = vec![false; terrain.heights.len()];
+    for spring in springs {
+        let mut bed = u8::MAX;
+        let mut current = Some(spring);
+        while let Some(i) = current {
+            // the river ends in a lake, carving on would drain it
+            if lake[i] {
+                break;
+            }
+            bed = bed.min(terrain.heights[i].saturating_sub(settings.depth).max(1));
+            for n in terrain.around(i, settings.radius) {
+                terrain.heights[n] = terrain.heights[n].min(bed);
+                river[n] = true;
+            }
+            current = flood.downstream[i];
+        }
+    }
+
+    // carving may only lower the terrain, the lakes are flooded again on the carved terrain
+    let flood = terrain.flood();
+    let mut water = ChunkStorage::new();
+    for (coord, chunk) in ground.iter_mut() {
+        let mut c = VoxelStorage::empty();
+        for lz in 0..64 {
+            for lx in 0..64 {
+                let i = Terrain::index(xs, zs, *coord, [lx, lz], terrain.width);
+                let height = terrain.heights[i] as u32;
+                chunk.set_pillar([lx, lz], chunk.get_pillar([lx, lz]) & low_bits(height));
+                let level = if river[i] {
+                    (flood.levels[i] as u32).max(height + 1)
+                } else {
+                    flood.levels[i] as u32
+                };
+                c.set_pillar([lx, lz], low_bits(level) & !low_bits(height));
+            }
+        }
+        water.insert(*coord, c);
+    }
+    water
+}
+
+/// splitmix64, spreads the springs evenly over the candidates
+fn hash(v: u64) -> u64 {
+    let mut z = v.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::voxel_storage::{low_bits, GenSettings, VoxelWorld};
+    use crate::water_sim::simulate_water;
+
+    use super::{rivers_and_lakes, RiverSettings};
+
+    fn total(world: &VoxelWorld) -> u64 {
+        world.water.values().map(|c| c.count()).sum()
+    }
+
+    /// one chunk shaped by `height`, without water
+    fn terrain(height: impl Fn(i32, i32) -> u32) -> VoxelWorld {
+        let mut world = VoxelWorld::empty(0..1, 0..1);
+        let ground = world.ground.get_mut(&[0, 0]).unwrap();
+        for x in 0..64 {
+            for z in 0..64 {
+                ground.set_pillar([x, z], low_bits(height(x as i32, z as i32)));
+            }
+        }
+        world
+    }
+
+    #[test]
+    fn lake_fills_up_to_the_rim() {
+        // a bowl with its lowest rim cell at height 20
+        let mut world = terrain(|x, z| {
+            let d = (x - 32).abs().max((z - 32).abs());
+            if (10..=12).contains(&d) {
+                if x == 32 && z < 32 {
+                    20
+                } else {
+                    30
+                }
+            } else {
+                10
+            }
+        });
+        let settings = RiverSettings {
+            springs: 0,
+            ..Default::default()
+        };
+        world.water = rivers_and_lakes(&world.xs, &world.zs, &mut world.ground, &settings);
+        assert!(world.is_water([32, 19, 32]));
+        assert!(!world.is_water([32, 20, 32]));
+        assert!(world.is_water([25, 10, 25]));
+        // outside of the bowl the water drains to the border
+        assert!(!world.is_water([2, 10, 2]));
+        for i in 0..16 {
+            assert_eq!(simulate_water(&mut world, i), 0);
+        }
+    }
+
+    #[test]
+    fn rivers_run_downhill_to_the_border() {
+        // a valley along z = 32 falling towards x = 0
+        let mut world = terrain(|x, z| (5 + x / 2 + (z - 32).abs() / 4) as u32);
+        let settings = RiverSettings {
+            springs: 1,
+            spring_height: 40,
+            ..Default::default()
+        };
+        let before = world.ground[&[0, 0]].raw.clone();
+        world.water = rivers_and_lakes(&world.xs, &world.zs, &mut world.ground, &settings);
+        let ground = &world.ground[&[0, 0]];
+        for (b, a) in before.iter().zip(ground.raw.iter()) {
+            assert_eq!(a & !b, 0, "carving never adds ground");
+        }
+        let carved: Vec<usize> = (0..64 * 64)
+            .filter(|i| before[*i] != ground.raw[*i])
+            .collect();
+        // from the spring down to the lowest border
+        assert!(carved.iter().any(|i| 64 - before[*i].leading_zeros() >= 40));
+        assert!(carved.iter().any(|i| i % 64 == 0));
+        for i in carved {
+            // one cell of water on top of the bed
+            let height = 64 - ground.raw[i].leading_zeros();
+            assert_eq!(world.water[&[0, 0]].raw[i], 1 << height);
+        }
+    }
+
+    #[test]
+    fn chunks_further_apart_than_i8() {
+        let mut world = VoxelWorld::empty(-128..1, 0..1);
+        world
+            .ground
+            .get_mut(&[0, 0])
+            .unwrap()
+            .set_pillar([10, 10], low_bits(50));
+        let settings = RiverSettings {
+            springs: 1,
+            ..Default::default()
+        };
+        world.water = rivers_and_lakes(&world.xs, &world.zs, &mut world.ground, &settings);
+        // the single peak is carved down to the river running off it
+        assert!(!world.is_ground([10, 2, 10]));
+        assert!(world.is_water([10, 1, 10]));
+    }
+
+    #[test]
+    fn generated_rivers_start_near_equilibrium() {
+        let settings = GenSettings {
+            rivers: Some(RiverSettings::default()),
+            ..Default::default()
+        };
+        let mut world = VoxelWorld::generate(-1..1, -1..1, &settings);
+        let volume = total(&world);
+        assert!(volume > 0);
+        let moved: u64 = (0..8).map(|i| simulate_water(&mut world, i)).sum();
+        assert!(moved * 10 < volume, "{moved} of {volume}");
+    }
+}
@@ -1,14 +1,45 @@
+pub mod blocky_mesh;
+mod buoyancy;
+pub mod caves;
+pub mod components;
+pub mod erosion;
+pub mod heightmap;
+pub mod hydrology;
+pub mod lod;
+pub mod mesh_data;
+pub mod mesh_export;
+pub mod navigation;
+pub mod pathfinding;
+pub mod prefab;
+pub mod raycast;
+pub mod save;
+pub mod smooth_mesh;
+mod stats_overlay;
+pub mod vox;
 mod voxel_mesh;
-mod voxel_storage;
-mod water_sim;
+pub mod voxel_storage;
+pub mod water_query;
+pub mod water_sim;
+pub mod water_stats;
 
+use godot::engine::file_access::ModeFlags;
+use godot::engine::image::Format;
+use godot::engine::CollisionShape3D;
+use godot::engine::ConcavePolygonShape3D;
 use godot::engine::EditorInterface;
 use godot::engine::Engine;
+use godot::engine::FileAccess;
 use godot::engine::GeometryInstance3D;
+use godot::engine::Image;
+use godot::engine::ImageTexture;
 use godot::engine::MeshInstance3D;
+use godot::engine::NavigationLink3D;
+use godot::engine::NavigationMesh;
+use godot::engine::NavigationRegion3D;
 use godot::engine::ResourceLoader;
 use godot::engine::Shader;
 use godot::engine::ShaderMaterial;
+use godot::engine::StaticBody3D;
 use godot::engine::WorkerThreadPool;
 use godot::obj::WithBaseField;
 use godot::prelude::*;
@@ -32,9 +63,53 @@ use godot::engine::ArrayMesh;
 use godot::engine::IEditorPlugin;
 use godot::obj::Gd;
 
-use crate::voxel_storage::VoxelStorage;
+use crate::blocky_mesh::AmbientOcclusion;
+use crate::blocky_mesh::PlanarUv;
+use crate::caves::CaveSettings;
+use crate::components::label_air;
+use crate::components::Components;
+use crate::erosion::ErosionSettings;
+use crate::heightmap::world_from_heightmap;
+use crate::heightmap::Heightmap;
+use crate::heightmap::HeightmapSettings;
+use crate::hydrology::RiverSettings;
+use crate::lod::downsample;
+use crate::lod::LOD_FACTORS;
+use crate::mesh_export::chunk_meshes;
+use crate::mesh_export::merge;
+use crate::mesh_export::write_glb;
+use crate::mesh_export::write_gltf;
+use crate::mesh_export::write_obj;
+use crate::navigation::bake_chunk;
+use crate::navigation::NavMeshData;
+use crate::navigation::NavSettings;
+use crate::navigation::WaterMode;
+use crate::pathfinding::find_path;
+use crate::pathfinding::snap_down;
+use crate::pathfinding::PathSettings;
+use crate::prefab::PrefabSettings;
+use crate::raycast::raycast;
+use crate::raycast::HitKind;
+use crate::save::load_world;
+use crate::save::save_world;
+use crate::smooth_mesh::binary_density;
+use crate::smooth_mesh::surface_nets;
+use crate::vox::read_vox;
+use crate::vox::write_vox;
+use crate::vox::VoxPalette;
+use crate::voxel_storage::BiomeMap;
+use crate::voxel_storage::BiomeSettings;
+use crate::voxel_storage::GenSettings;
 use crate::voxel_storage::VoxelWorld;
-use crate::water_sim::simulate_water;
+use crate::water_query::sample_water;
+use crate::water_query::WaterSample;
+use crate::water_sim::simulate_water_tracked;
+use crate::water_sim::FlowField;
+use crate::water_sim::FlowTracker;
+use crate::water_stats::WaterStatistics;
+
+/// number of simulation steps the flow field shown on the water is averaged over
+const FLOW_WINDOW: usize = 16;
 
 #[godot_api]
 impl IEditorPlugin for WorldGen {
@@ -82,9 +157,9 @@ impl GenMeshNode {
             world_node.set_owner(parent.clone());
             let children = world_node.call("initialize".into(), &[]);
             let children: Array<Gd<Node>> = children.to();
-            for mut child in children.iter_shared() {
+            for child in children.iter_shared() {
                 world_node.clone().add_child(child.clone());
-                child.set_owner(parent.clone());
+                set_owner_recursive(child, parent.clone());
             }
         }
     }
@@ -102,11 +177,16 @@ impl GenMeshNode {
     }
 }
 
-fn create_ground_mesh(p: Vector3, storage: &VoxelStorage) -> Gd<Node> {
-    let mesh: Gd<ArrayMesh> = voxel_mesh::blocky(&storage.visible_faces());
-    let mut instance = MeshInstance3D::new_alloc();
-    instance.set_mesh(mesh.upcast());
-    let mut geo = instance.clone().upcast::<GeometryInstance3D>();
+/// owner of the node and all its descendants, so they are saved with the edited scene
+fn set_owner_recursive(mut node: Gd<Node>, owner: Gd<Node>) {
+    node.set_owner(owner.clone());
+    for child in node.get_children().iter_shared() {
+        set_owner_recursive(child, owner.clone());
+    }
+}
+
+/// without a biome map the ground is colored by height only
+fn ground_material(biomes: Option<Gd<ImageTexture>>) -> Gd<ShaderMaterial> {
     let mut sh = ShaderMaterial::new_gd();
     let shader: Gd<Shader> = ResourceLoader::load(
         &mut ResourceLoader::singleton(),
@@ -115,14 +195,104 @@ fn create_ground_mesh(p: Vector3, storage: &VoxelStorage) -> Gd<Node> {
     .unwrap()
     .cast();
     sh.set_shader(shader);
-    geo.set_material_override(sh.upcast());
+    if let Some(biomes) = biomes {
+        sh.set_shader_parameter("biomes".into(), true.to_variant());
+        sh.set_shader_parameter("biome_map".into(), biomes.to_variant());
+    }
+    sh
+}
+
+/// 64 x 64 RGB8 texture of the weights of desert, plains and mountains per pillar of the chunk
+fn biome_texture(biomes: &BiomeMap, coord: [i8; 2]) -> Gd<ImageTexture> {
+    let mut bytes = Vec::with_capacity(64 * 64 * 3);
+    for z in 0..64 {
+        for x in 0..64 {
+            let p = [coord[0] as i32 * 64 + x, coord[1] as i32 * 64 + z];
+            for w in biomes.weights(p) {
+                bytes.push((w * 255.0).round() as u8);
+            }
+        }
+    }
+    let image = Image::create_from_data(
+        64,
+        64,
+        false,
+        Format::RGB8,
+        PackedByteArray::from(bytes.as_slice()),
+    )
+    .unwrap();
+    ImageTexture::create_from_image(image).unwrap()
+}
+
+/// ground mesh with a static body child named "collision" holding the trimesh shape "shape".
+/// Each of `lods` becomes a child "lod_<factor>", the full resolution mesh is visible up to
+/// `lod_distance` and every further level up to twice the distance of the previous one.
+fn create_ground_mesh(
+    p: Vector3,
+    mesh: Gd<ArrayMesh>,
+    faces: PackedVector3Array,
+    lods: Vec<(u8, Gd<ArrayMesh>)>,
+    lod_distance: f32,
+    biomes: Option<Gd<ImageTexture>>,
+) -> Gd<Node> {
+    let mut instance = MeshInstance3D::new_alloc();
+    instance.set_mesh(mesh.upcast());
+    instance.add_child(create_ground_collision(faces).upcast());
+    let material = ground_material(biomes);
+    let mut geo = instance.clone().upcast::<GeometryInstance3D>();
+    geo.set_material_override(material.clone().upcast());
+    if !lods.is_empty() {
+        geo.set_visibility_range_end(lod_distance);
+    }
+    let levels = lods.len();
+    let mut begin = lod_distance;
+    for (i, (factor, mesh)) in lods.into_iter().enumerate() {
+        let mut lod = create_lod_mesh(factor, mesh, material.clone());
+        lod.set_visibility_range_begin(begin);
+        begin *= 2.0;
+        // the coarsest level stays visible at any distance
+        if i + 1 < levels {
+            lod.set_visibility_range_end(begin);
+        }
+        instance.add_child(lod.upcast());
+    }
     let mut transform: Gd<Node3D> = instance.clone().upcast();
     transform.set_position(p);
     instance.upcast()
 }
 
-fn create_water_mesh(p: Vector3, storage: &VoxelStorage) -> Gd<Node> {
-    let mesh: Gd<ArrayMesh> = voxel_mesh::blocky(&storage.visible_faces());
+/// mesh of a downsampled chunk, scaled up by its factor to cover the whole chunk
+fn create_lod_mesh(
+    factor: u8,
+    mesh: Gd<ArrayMesh>,
+    material: Gd<ShaderMaterial>,
+) -> Gd<MeshInstance3D> {
+    let mut instance = MeshInstance3D::new_alloc();
+    instance.set_name(format!("lod_{factor}").into());
+    instance.set_mesh(mesh.upcast());
+    instance.set_scale(Vector3::ONE * factor as f32);
+    instance.set_material_override(material.upcast());
+    instance.set_instance_shader_parameter("voxel_scale".into(), (factor as f32).to_variant());
+    instance
+}
+
+fn create_ground_shape(faces: PackedVector3Array) -> Gd<ConcavePolygonShape3D> {
+    let mut shape = ConcavePolygonShape3D::new_gd();
+    shape.set_faces(faces);
+    shape
+}
+
+fn create_ground_collision(faces: PackedVector3Array) -> Gd<StaticBody3D> {
+    let mut collision = CollisionShape3D::new_alloc();
+    collision.set_name("shape".into());
+    collision.set_shape(create_ground_shape(faces).upcast());
+    let mut body = StaticBody3D::new_alloc();
+    body.set_name("collision".into());
+    body.add_child(collision.upcast());
+    body
+}
+
+fn create_water_mesh(p: Vector3, mesh: Gd<ArrayMesh>, flow: Option<&FlowField>) -> Gd<Node> {
     let mut instance = MeshInstance3D::new_alloc();
     instance.set_mesh(mesh.upcast());
     let mut geo = instance.clone().upcast::<GeometryInstance3D>();
@@ -134,6 +304,7 @@ fn create_water_mesh(p: Vector3, storage: &VoxelStorage) -> Gd<Node> {
     .unwrap()
     .cast();
     sh.set_shader(shader);
+    sh.set_shader_parameter("flow_map".into(), flow_texture(flow).to_variant());
     geo.set_material_override(sh.upcast());
     geo.set_transparency(0.5);
     let mut transform: Gd<Node3D> = instance.clone().upcast();
@@ -141,25 +312,187 @@ fn create_water_mesh(p: Vector3, storage: &VoxelStorage) -> Gd<Node> {
     instance.upcast()
 }
 
+/// 64 x 64 RGF texture, red is the flow in x and green the flow in z direction
+fn flow_texture(flow: Option<&FlowField>) -> Gd<ImageTexture> {
+    let samples = flow
+        .map(|f| f.samples())
+        .unwrap_or_else(|| vec![[0.0; 2]; 64 * 64]);
+    let mut bytes = Vec::with_capacity(samples.len() * 8);
+    for [x, z] in samples {
+        bytes.extend_from_slice(&x.to_le_bytes());
+        bytes.extend_from_slice(&z.to_le_bytes());
+    }
+    let image = Image::create_from_data(
+        64,
+        64,
+        false,
+        Format::RGF,
+        PackedByteArray::from(bytes.as_slice()),
+    )
+    .unwrap();
+    ImageTexture::create_from_image(image).unwrap()
+}
+
+/// world nodes add themselves to the "world" group when they enter the tree
+fn find_world(tree: Option<Gd<SceneTree>>) -> Option<Gd<World>> {
+    let node = tree?.get_first_node_in_group("world".into())?;
+    node.try_cast::<World>().ok()
+}
+
+fn create_navigation_mesh(nav: &NavMeshData) -> Gd<NavigationMesh> {
+    let mut mesh = NavigationMesh::new_gd();
+    let mut vertices = PackedVector3Array::new();
+    for &[x, y, z] in nav.vertices.iter() {
+        vertices.push(Vector3::new(x, y, z));
+    }
+    mesh.set_vertices(vertices);
+    for polygon in nav.polygons.iter() {
+        mesh.add_polygon(PackedInt32Array::from(polygon.as_slice()));
+    }
+    mesh
+}
+
+fn create_navigation_region(p: Vector3, nav: &NavMeshData, travel_cost: f32) -> Gd<Node> {
+    let mut region = NavigationRegion3D::new_alloc();
+    region.set_navigation_mesh(create_navigation_mesh(nav));
+    region.set_travel_cost(travel_cost);
+    region.set_position(p);
+    set_navigation_links(&mut region, nav, travel_cost);
+    region.upcast()
+}
+
+/// replaces the `NavigationLink3D` children of the region with the step links of the mesh,
+/// they cost as much as the region
+fn set_navigation_links(region: &mut Gd<NavigationRegion3D>, nav: &NavMeshData, travel_cost: f32) {
+    for child in region.get_children().iter_shared() {
+        if let Ok(mut link) = child.try_cast::<NavigationLink3D>() {
+            region.remove_child(link.clone().upcast());
+            link.queue_free();
+        }
+    }
+    for [start, end] in nav.links.iter() {
+        let mut link = NavigationLink3D::new_alloc();
+        link.set_start_position(Vector3::new(start[0], start[1], start[2]));
+        link.set_end_position(Vector3::new(end[0], end[1], end[2]));
+        link.set_bidirectional(true);
+        link.set_travel_cost(travel_cost);
+        region.add_child(link.upcast());
+    }
+}
+
+fn chunk_node_name(kind: &str, coord: &[i8; 2]) -> String {
+    format!("{kind}_{}_{}", coord[0], coord[1])
+}
+
 #[derive(GodotClass)]
 #[class(base=Node3D)]
 struct World {
     base: Base<Node3D>,
     voxels: VoxelWorld,
+    flow: FlowTracker,
+    moving_cells: u64,
+    /// connected air of the current voxels for `is_sealed`, cleared whenever the voxels change
+    air_components: Option<Components>,
+    /// free voxels an agent needs above the floor
+    #[export]
+    agent_height: i32,
+    /// largest height difference in voxels agents can step up or down
+    #[export]
+    step_height: i32,
+    /// largest height in voxels agents drop down from a ledge
+    #[export]
+    max_drop: i32,
+    /// water covered floor is walkable at `water_travel_cost`, otherwise it is left out
+    #[export]
+    water_walkable: bool,
+    #[export]
+    water_travel_cost: f32,
+    /// ground is meshed with surface nets instead of cubes
+    #[export]
+    smooth_terrain: bool,
+    /// camera distance at which chunks switch to half resolution, each further level starts at
+    /// twice the distance. Zero or less disables the lower levels of detail
+    #[export]
+    lod_distance: f32,
+    /// how far the water surface sits below the top of its voxels
+    #[export]
+    water_surface_lowering: f32,
+    /// palette index of ground in exported `.vox` files
+    #[export]
+    vox_ground_index: i32,
+    /// palette index marking water in `.vox` files, all other indices are ground
+    #[export]
+    vox_water_index: i32,
+    /// droplets per chunk `regenerate` erodes the terrain with, 0 disables erosion
+    #[export]
+    erosion_iterations: i32,
+    /// `regenerate` carves cheese and worm caves into the ground
+    #[export]
+    caves: bool,
+    /// cheese caves open where the cave noise exceeds this, 1 or more disables them
+    #[export]
+    cave_cheese_threshold: f32,
+    /// width of the worm caves, 0 disables them
+    #[export]
+    cave_worm_radius: f32,
+    /// `regenerate` blends desert, plains and mountain terrain, each with its own water level
+    #[export]
+    biomes: bool,
+    /// distance in climate space over which biomes blend, larger values give wider transitions
+    #[export]
+    biome_blend: f32,
+    /// `regenerate` carves this many rivers from springs and fills the lakes of the terrain,
+    /// 0 keeps the flat water layer
+    #[export]
+    river_springs: i32,
+    /// `regenerate` places trees, ruins and bridges on the terrain
+    #[export]
+    prefabs: bool,
+    /// biomes of the generated world, None for imported worlds
+    biome_map: Option<BiomeMap>,
 }
 
 #[godot_api]
 impl INode3D for World {
     fn init(base: Base<Node3D>) -> Self {
         let mut world = VoxelWorld::gen(-2..2, -2..2);
+        let mut flow = FlowTracker::new(FLOW_WINDOW);
+        let mut moving_cells = 0;
         for i in 0..128u8 {
-            simulate_water(&mut world, i as u8);
+            moving_cells = simulate_water_tracked(&mut world, i as u8, &mut flow);
         }
         World {
             base,
             voxels: world,
+            flow,
+            moving_cells,
+            air_components: None,
+            agent_height: 2,
+            step_height: 1,
+            max_drop: 3,
+            water_walkable: true,
+            water_travel_cost: 4.0,
+            smooth_terrain: false,
+            lod_distance: 128.0,
+            water_surface_lowering: 0.1,
+            vox_ground_index: 1,
+            vox_water_index: 2,
+            erosion_iterations: 0,
+            caves: false,
+            cave_cheese_threshold: 0.6,
+            cave_worm_radius: 0.06,
+            biomes: false,
+            biome_blend: 0.15,
+            river_springs: 0,
+            prefabs: false,
+            biome_map: None,
         }
     }
+
+    fn ready(&mut self) {
+        // groups added in the editor are not saved with the scene
+        self.base_mut().add_to_group("world".into());
+    }
 }
 
 #[godot_api]
@@ -167,33 +500,565 @@ impl World {
     #[func]
     fn initialize(&mut self) -> Array<Gd<Node>> {
         let mut r = Array::new();
-        for (coord, s) in self.voxels.ground.iter() {
-            let w = &self.voxels.water[coord];
-            let ground = create_ground_mesh(
+        for coord in self.voxels.ground.keys() {
+            let (mesh, faces) = self.ground_mesh(*coord);
+            let mut ground = create_ground_mesh(
                 Vector3::new(coord[0] as f32 * 64.0, 0.0, coord[1] as f32 * 64.0),
-                s,
+                mesh,
+                faces,
+                self.lod_meshes(*coord),
+                self.lod_distance,
+                self.biome_map.as_ref().map(|b| biome_texture(b, *coord)),
             );
+            ground.set_name(chunk_node_name("ground", coord).into());
             r.push(ground);
             // p.add_child(ground.clone());
             // ground.set_owner(p.upcast());
             let mut water = create_water_mesh(
                 Vector3::new(coord[0] as f32 * 64.0, 0.0, coord[1] as f32 * 64.0),
-                w,
+                self.water_mesh(*coord),
+                self.flow.get(*coord),
             );
+            water.set_name(chunk_node_name("water", coord).into());
             water.add_to_group("Water".into());
             r.push(water);
+            let nav = bake_chunk(&self.voxels, *coord, &self.nav_settings());
+            let p = Vector3::new(coord[0] as f32 * 64.0, 0.0, coord[1] as f32 * 64.0);
+            let mut land = create_navigation_region(p, &nav.land, 1.0);
+            land.set_name(chunk_node_name("navigation", coord).into());
+            r.push(land);
+            let mut water = create_navigation_region(p, &nav.water, self.water_travel_cost);
+            water.set_name(chunk_node_name("navigation_water", coord).into());
+            r.push(water);
         }
         r
     }
 
+    fn nav_settings(&self) -> NavSettings {
+        NavSettings {
+            agent_height: self.agent_height.clamp(1, 64) as u8,
+            step_height: self.step_height.clamp(0, 63) as u8,
+            water: if self.water_walkable {
+                WaterMode::Costly
+            } else {
+                WaterMode::Impassable
+            },
+        }
+    }
+
+    /// re-bakes the navigation regions of the chunk
+    fn rebuild_navigation(&self, coord: [i8; 2]) {
+        if !self.voxels.ground.contains_key(&coord) {
+            return;
+        }
+        let nav = bake_chunk(&self.voxels, coord, &self.nav_settings());
+        for (kind, data, travel_cost) in [
+            ("navigation", &nav.land, 1.0),
+            ("navigation_water", &nav.water, self.water_travel_cost),
+        ] {
+            let name = chunk_node_name(kind, &coord);
+            if let Some(mut region) = self
+                .base()
+                .try_get_node_as::<NavigationRegion3D>(name.as_str())
+            {
+                region.set_navigation_mesh(create_navigation_mesh(data));
+                set_navigation_links(&mut region, data, travel_cost);
+            }
+        }
+    }
+
     #[func]
     fn simulate_step(&mut self) {
-        simulate_water(&mut self.voxels, 0);
-        let children = self.base().get_children();
-        for child in children.iter_shared() {
-            if child.is_in_group("water".into()) {
-                godot_print!("found water node");
+        self.moving_cells = simulate_water_tracked(&mut self.voxels, 0, &mut self.flow);
+        self.air_components = None;
+        let coords: Vec<[i8; 2]> = self.voxels.water.keys().cloned().collect();
+        for coord in coords {
+            let name = chunk_node_name("water", &coord);
+            let Some(geo) = self
+                .base()
+                .try_get_node_as::<GeometryInstance3D>(name.as_str())
+            else {
+                continue;
+            };
+            let Some(material) = geo.get_material_override() else {
+                continue;
+            };
+            let mut sh: Gd<ShaderMaterial> = material.cast();
+            sh.set_shader_parameter(
+                "flow_map".into(),
+                flow_texture(self.flow.get(coord)).to_variant(),
+            );
+        }
+    }
+
+    /// places (solid) or removes ground at the voxel position, relative to the world node.
+    /// Placed ground displaces water. The meshes and the collision of the chunk are rebuilt.
+    #[func]
+    fn set_ground(&mut self, voxel: Vector3i, solid: bool) -> bool {
+        let Some((chunk, local)) = self.voxels.locate([voxel.x, voxel.y, voxel.z]) else {
+            return false;
+        };
+        self.air_components = None;
+        let ground = self.voxels.ground.get_mut(&chunk).unwrap();
+        if solid {
+            ground.set(local);
+            self.voxels.water.get_mut(&chunk).unwrap().unset(local);
+        } else {
+            ground.unset(local);
+        }
+        self.rebuild_chunk(chunk);
+        // the ambient occlusion of the neighbouring chunk meshes reaches across the border
+        for (border, neighbour) in [
+            (local[0] == 0, [chunk[0].saturating_sub(1), chunk[1]]),
+            (local[0] == 63, [chunk[0].saturating_add(1), chunk[1]]),
+            (local[2] == 0, [chunk[0], chunk[1].saturating_sub(1)]),
+            (local[2] == 63, [chunk[0], chunk[1].saturating_add(1)]),
+        ] {
+            if border && neighbour != chunk && self.voxels.ground.contains_key(&neighbour) {
+                self.rebuild_chunk(neighbour);
+            }
+        }
+        self.rebuild_navigation(chunk);
+        // steps into this chunk are linked by the neighbours in -x and -z direction
+        if local[0] == 0 {
+            self.rebuild_navigation([chunk[0].saturating_sub(1), chunk[1]]);
+        }
+        if local[2] == 0 {
+            self.rebuild_navigation([chunk[0], chunk[1].saturating_sub(1)]);
+        }
+        true
+    }
+
+    /// mesh and collision triangles of the ground of a chunk, blocky or smooth depending on
+    /// `smooth_terrain`
+    fn ground_mesh(&self, coord: [i8; 2]) -> (Gd<ArrayMesh>, PackedVector3Array) {
+        if self.smooth_terrain {
+            let origin = [coord[0] as i32 * 64, coord[1] as i32 * 64];
+            // below the world counts as ground, so the bottom of the world stays closed
+            let mesh = surface_nets(|p| {
+                binary_density(
+                    p[1] < 0
+                        || self
+                            .voxels
+                            .is_ground([origin[0] + p[0], p[1], origin[1] + p[2]]),
+                )
+            });
+            (
+                voxel_mesh::to_array_mesh(&mesh),
+                voxel_mesh::collision_faces(&mesh),
+            )
+        } else {
+            let mesh = blocky_mesh::chunk_ground(&self.voxels, coord);
+            (
+                voxel_mesh::to_array_mesh(&mesh),
+                voxel_mesh::collision_faces(&mesh),
+            )
+        }
+    }
+
+    /// downsampled blocky meshes of the ground of a chunk per factor, there are no lower levels
+    /// of detail for smooth terrain
+    fn lod_meshes(&self, coord: [i8; 2]) -> Vec<(u8, Gd<ArrayMesh>)> {
+        if self.smooth_terrain || self.lod_distance <= 0.0 {
+            return Vec::new();
+        }
+        LOD_FACTORS
+            .iter()
+            .map(|&factor| {
+                let reduced = downsample(&self.voxels.ground[&coord], factor);
+                let cells = 64 / factor as i32;
+                // occlusion only inside of the chunk, neighbours may use a different resolution
+                let occluder = |p: [i32; 3]| {
+                    p.iter().all(|c| (0..cells).contains(c))
+                        && reduced.get([p[0] as u8, p[1] as u8, p[2] as u8])
+                };
+                let mesh = blocky_mesh::mesh(
+                    &reduced.visible_faces(),
+                    &[&PlanarUv, &AmbientOcclusion(occluder)],
+                );
+                (factor, voxel_mesh::to_array_mesh(&mesh))
+            })
+            .collect()
+    }
+
+    fn rebuild_chunk(&self, coord: [i8; 2]) {
+        let (ground_mesh, faces) = self.ground_mesh(coord);
+        let name = chunk_node_name("ground", &coord);
+        if let Some(mut mesh) = self.base().try_get_node_as::<MeshInstance3D>(name.as_str()) {
+            mesh.set_mesh(ground_mesh.upcast());
+        }
+        for (factor, lod_mesh) in self.lod_meshes(coord) {
+            let lod_path = format!("{name}/lod_{factor}");
+            if let Some(mut mesh) = self
+                .base()
+                .try_get_node_as::<MeshInstance3D>(lod_path.as_str())
+            {
+                mesh.set_mesh(lod_mesh.upcast());
+            }
+        }
+        let shape_path = format!("{name}/collision/shape");
+        if let Some(mut shape) = self
+            .base()
+            .try_get_node_as::<CollisionShape3D>(shape_path.as_str())
+        {
+            shape.set_shape(create_ground_shape(faces).upcast());
+        }
+        let water_name = chunk_node_name("water", &coord);
+        if let Some(mut mesh) = self
+            .base()
+            .try_get_node_as::<MeshInstance3D>(water_name.as_str())
+        {
+            mesh.set_mesh(self.water_mesh(coord).upcast());
+        }
+    }
+
+    /// only the faces of the water bordering air
+    fn water_mesh(&self, coord: [i8; 2]) -> Gd<ArrayMesh> {
+        voxel_mesh::to_array_mesh(&blocky_mesh::chunk_water(
+            &self.voxels,
+            coord,
+            self.water_surface_lowering,
+        ))
+    }
+
+    fn sample_water_at(&self, position: Vector3) -> WaterSample {
+        let local = self.base().to_local(position);
+        sample_water(&self.voxels, &self.flow, [local.x, local.y, local.z])
+    }
+
+    /// true if the global position is inside of a water voxel
+    #[func]
+    fn is_submerged(&self, position: Vector3) -> bool {
+        self.sample_water_at(position).submerged
+    }
+
+    /// distance of the global position below the water surface, 0 above water
+    #[func]
+    fn water_depth(&self, position: Vector3) -> f32 {
+        self.sample_water_at(position).depth.max(0.0)
+    }
+
+    /// global height of the water surface at or below the position, NAN if there is none
+    #[func]
+    fn water_surface_height(&self, position: Vector3) -> f32 {
+        let local = self.base().to_local(position);
+        match self.sample_water_at(position).surface_height {
+            Some(h) => self.base().to_global(Vector3::new(local.x, h, local.z)).y,
+            None => f32::NAN,
+        }
+    }
+
+    /// global direction the water at the position flows in, length is the flow in cells per step
+    #[func]
+    fn water_flow_direction(&self, position: Vector3) -> Vector3 {
+        let [x, z] = self.sample_water_at(position).flow;
+        self.base().get_global_transform().basis * Vector3::new(x, 0.0, z)
+    }
+    fn water_statistics(&self) -> WaterStatistics {
+        WaterStatistics::collect(&self.voxels, self.moving_cells)
+    }
+
+    /// total_volume, chunk_volume (keyed by chunk coordinate), moving_cells and basins (volume, surface_level)
+    #[func]
+    fn statistics(&self) -> Dictionary {
+        let stats = self.water_statistics();
+        let mut chunk_volume = Dictionary::new();
+        for (coord, volume) in stats.chunk_volume.iter() {
+            chunk_volume.set(
+                Vector2i::new(coord[0] as i32, coord[1] as i32),
+                *volume as i64,
+            );
+        }
+        let mut basins: Array<Dictionary> = Array::new();
+        for basin in stats.basins.iter() {
+            let mut b = Dictionary::new();
+            b.set("volume", basin.volume as i64);
+            b.set("surface_level", basin.surface_level as i64);
+            basins.push(b);
+        }
+        let mut r = Dictionary::new();
+        r.set("total_volume", stats.total_volume as i64);
+        r.set("chunk_volume", chunk_volume);
+        r.set("moving_cells", stats.moving_cells as i64);
+        r.set("basins", basins);
+        r
+    }
+    /// true if the empty space containing the global position is enclosed by ground,
+    /// so water poured into it can not leave
+    #[func]
+    fn is_sealed(&mut self, position: Vector3) -> bool {
+        let local = self.base().to_local(position);
+        let voxel = VoxelWorld::voxel_at([local.x, local.y, local.z]);
+        let voxels = &self.voxels;
+        self.air_components
+            .get_or_insert_with(|| label_air(voxels))
+            .component_at(&self.voxels, voxel)
+            .map(|c| c.is_sealed())
+            .unwrap_or(false)
+    }
+    /// first voxel hit by the ray, origin and direction are global
+    /// returns an empty dictionary on a miss, otherwise voxel (Vector3i), normal (Vector3i),
+    /// position (global Vector3), distance and kind ("ground" or "water")
+    #[func]
+    fn raycast(
+        &self,
+        origin: Vector3,
+        direction: Vector3,
+        max_distance: f32,
+        hit_water: bool,
+    ) -> Dictionary {
+        let mut r = Dictionary::new();
+        let local_origin = self.base().to_local(origin);
+        let local_direction = self.base().get_global_transform().basis.inverse() * direction;
+        let Some(hit) = raycast(
+            &self.voxels,
+            [local_origin.x, local_origin.y, local_origin.z],
+            [local_direction.x, local_direction.y, local_direction.z],
+            max_distance,
+            hit_water,
+        ) else {
+            return r;
+        };
+        let position = local_origin + local_direction.normalized() * hit.distance;
+        r.set(
+            "voxel",
+            Vector3i::new(hit.voxel[0], hit.voxel[1], hit.voxel[2]),
+        );
+        r.set(
+            "normal",
+            Vector3i::new(hit.normal[0], hit.normal[1], hit.normal[2]),
+        );
+        r.set("position", self.base().to_global(position));
+        r.set("distance", hit.distance);
+        r.set(
+            "kind",
+            match hit.kind {
+                HitKind::Ground => "ground",
+                HitKind::Water => "water",
+            },
+        );
+        r
+    }
+    fn path_settings(&self) -> PathSettings {
+        PathSettings {
+            agent_height: self.agent_height.clamp(1, 64) as u8,
+            max_step: self.step_height.clamp(0, 63) as u8,
+            max_drop: self.max_drop.clamp(0, 63) as u8,
+            swim: self.water_walkable,
+            ..PathSettings::default()
+        }
+    }
+
+    /// voxel path between the global positions, as global positions at the bottom center of each cell
+    /// both ends are moved down onto the floor below them, empty if there is no path
+    #[func]
+    fn find_path(&self, from: Vector3, to: Vector3) -> PackedVector3Array {
+        let mut r = PackedVector3Array::new();
+        let settings = self.path_settings();
+        let from = self.base().to_local(from);
+        let to = self.base().to_local(to);
+        let Some(start) = snap_down(
+            &self.voxels,
+            VoxelWorld::voxel_at([from.x, from.y, from.z]),
+            &settings,
+        ) else {
+            return r;
+        };
+        let Some(goal) = snap_down(
+            &self.voxels,
+            VoxelWorld::voxel_at([to.x, to.y, to.z]),
+            &settings,
+        ) else {
+            return r;
+        };
+        if let Some(path) = find_path(&self.voxels, start, goal, &settings) {
+            for [x, y, z] in path {
+                r.push(self.base().to_global(Vector3::new(
+                    x as f32 + 0.5,
+                    y as f32,
+                    z as f32 + 0.5,
+                )));
             }
         }
+        r
+    }
+
+    fn vox_palette(&self) -> VoxPalette {
+        VoxPalette {
+            ground: self.vox_ground_index.clamp(1, 255) as u8,
+            water: self.vox_water_index.clamp(1, 255) as u8,
+        }
+    }
+
+    fn gen_settings(&self) -> GenSettings {
+        GenSettings {
+            erosion: (self.erosion_iterations > 0).then(|| ErosionSettings {
+                iterations: self.erosion_iterations as usize,
+                ..Default::default()
+            }),
+            caves: self.caves.then(|| CaveSettings {
+                cheese_threshold: self.cave_cheese_threshold as f64,
+                worm_radius: self.cave_worm_radius as f64,
+                ..Default::default()
+            }),
+            biomes: self.biomes.then(|| BiomeSettings {
+                blend: self.biome_blend as f64,
+                ..Default::default()
+            }),
+            rivers: (self.river_springs > 0).then(|| RiverSettings {
+                springs: self.river_springs as usize,
+                ..Default::default()
+            }),
+            prefabs: self.prefabs.then(PrefabSettings::default),
+        }
+    }
+
+    /// Replaces the voxels with a new world of the same chunks from the exported generation
+    /// settings and lets the water settle like on startup. Existing chunk nodes are left alone,
+    /// `initialize` creates the nodes of the new world
+    #[func]
+    fn regenerate(&mut self) {
+        let settings = self.gen_settings();
+        let mut world =
+            VoxelWorld::generate(self.voxels.xs.clone(), self.voxels.zs.clone(), &settings);
+        let mut flow = FlowTracker::new(FLOW_WINDOW);
+        for i in 0..128u8 {
+            self.moving_cells = simulate_water_tracked(&mut world, i, &mut flow);
+        }
+        self.voxels = world;
+        self.flow = flow;
+        self.air_components = None;
+        self.biome_map = settings.biomes.as_ref().map(BiomeMap::new);
+    }
+
+    /// replaces the voxels with the content of a MagicaVoxel file, existing chunk nodes are left
+    /// alone, `initialize` creates the nodes of the imported world
+    #[func]
+    fn import_vox(&mut self, path: GString) -> bool {
+        let bytes = FileAccess::get_file_as_bytes(path);
+        let Some(world) = read_vox(bytes.as_slice(), &self.vox_palette()) else {
+            return false;
+        };
+        self.voxels = world;
+        self.flow = FlowTracker::new(FLOW_WINDOW);
+        self.moving_cells = 0;
+        self.air_components = None;
+        self.biome_map = None;
+        true
+    }
+
+    /// replaces the voxels with a save file, e.g. one written by the worldtool binary, existing
+    /// chunk nodes are left alone like in `import_vox`
+    #[func]
+    fn load_world(&mut self, path: GString) -> bool {
+        let bytes = FileAccess::get_file_as_bytes(path);
+        let Some(world) = load_world(bytes.as_slice()) else {
+            return false;
+        };
+        self.voxels = world;
+        self.flow = FlowTracker::new(FLOW_WINDOW);
+        self.moving_cells = 0;
+        self.air_components = None;
+        self.biome_map = None;
+        true
+    }
+
+    #[func]
+    fn save_world(&self, path: GString) -> bool {
+        let Some(mut file) = FileAccess::open(path, ModeFlags::WRITE) else {
+            return false;
+        };
+        file.store_buffer(PackedByteArray::from(save_world(&self.voxels).as_slice()));
+        true
+    }
+
+    /// Writes the blocky ground and water meshes of all chunks, per chunk or `merged` into one
+    /// mesh per material. The extension of `path` picks the format: obj, gltf or glb.
+    #[func]
+    fn export_meshes(&self, path: GString, merged: bool) -> bool {
+        let mut meshes = chunk_meshes(&self.voxels, self.water_surface_lowering);
+        if merged {
+            meshes = merge(meshes);
+        }
+        let path_string = path.to_string();
+        let bytes = match path_string.rsplit('.').next() {
+            Some("obj") => write_obj(&meshes).into_bytes(),
+            Some("gltf") => write_gltf(&meshes).into_bytes(),
+            Some("glb") => write_glb(&meshes),
+            _ => return false,
+        };
+        let Some(mut file) = FileAccess::open(path, ModeFlags::WRITE) else {
+            return false;
+        };
+        file.store_buffer(PackedByteArray::from(bytes.as_slice()));
+        true
+    }
+
+    /// writes all chunks into a MagicaVoxel file, one model per chunk
+    #[func]
+    fn export_vox(&self, path: GString) -> bool {
+        let Some(mut file) = FileAccess::open(path, ModeFlags::WRITE) else {
+            return false;
+        };
+        let bytes = write_vox(&self.voxels, &self.vox_palette());
+        file.store_buffer(PackedByteArray::from(bytes.as_slice()));
+        true
+    }
+
+    /// Replaces the voxels with ground from a grayscale PNG, PGM or square raw 16 bit image, one
+    /// pillar per pixel and centered on the world origin. Ground reaches up to
+    /// `sample * scale + offset`, pixels of the optional water mask brighter than half are filled
+    /// with water up to `water_level`. Returns false if an image can not be read, the water mask
+    /// differs in size or the heightmap is wider than 127 chunks. `initialize` creates the nodes
+    /// of the new world.
+    #[func]
+    fn import_heightmap(
+        &mut self,
+        path: GString,
+        scale: f32,
+        offset: f32,
+        water_mask_path: GString,
+        water_level: i32,
+    ) -> bool {
+        let Some(heightmap) = Heightmap::decode(FileAccess::get_file_as_bytes(path).as_slice())
+        else {
+            return false;
+        };
+        let water_mask = if water_mask_path.is_empty() {
+            None
+        } else {
+            let Some(mask) =
+                Heightmap::decode(FileAccess::get_file_as_bytes(water_mask_path).as_slice())
+            else {
+                return false;
+            };
+            Some(mask)
+        };
+        let chunks = |pixels: usize| -> Option<std::ops::Range<i8>> {
+            let count = i8::try_from(pixels.div_ceil(64)).ok()?;
+            Some(-(count / 2)..count - count / 2)
+        };
+        let (Some(xs), Some(zs)) = (chunks(heightmap.width), chunks(heightmap.height)) else {
+            return false;
+        };
+        let Some(world) = world_from_heightmap(
+            xs,
+            zs,
+            &heightmap,
+            water_mask.as_ref(),
+            &HeightmapSettings {
+                scale,
+                offset,
+                water_level: water_level.clamp(0, 64) as u8,
+            },
+        ) else {
+            return false;
+        };
+        self.voxels = world;
+        self.flow = FlowTracker::new(FLOW_WINDOW);
+        self.moving_cells = 0;
+        self.air_components = None;
+        self.biome_map = None;
+        true
     }
 }
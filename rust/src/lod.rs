@@ -0,0 +1,85 @@
+use crate::voxel_storage::VoxelStorage;
+
+/// voxels per axis merged into one voxel for each level of detail after the full resolution
+pub const LOD_FACTORS: [u8; 3] = [2, 4, 8];
+
+/// Merges `factor` voxels per axis into one, a merged voxel is set if any of its voxels is set.
+/// The result occupies the first 64 / factor cells on every axis, the rest stays empty.
+/// Merging only ever adds volume, so a low resolution surface lies on or above the full
+/// resolution one, and the faces `visible_faces` emits at the chunk border close the gap
+/// between neighbouring chunks of different detail like a skirt.
+pub fn downsample(storage: &VoxelStorage, factor: u8) -> VoxelStorage {
+    assert!(
+        factor.is_power_of_two() && factor <= 32,
+        "lod factor {factor} has to be a power of two up to 32"
+    );
+    let f = factor as usize;
+    let mut reduced = VoxelStorage::empty();
+    for z in 0..64 / f {
+        for x in 0..64 / f {
+            let mut pillar = 0;
+            for dz in 0..f {
+                for dx in 0..f {
+                    pillar |= storage.raw[x * f + dx + (z * f + dz) * 64];
+                }
+            }
+            reduced.raw[x + z * 64] = reduce_pillar(pillar, factor);
+        }
+    }
+    reduced
+}
+
+/// bit y of the result is set if any of the bits y * factor..(y + 1) * factor is set
+fn reduce_pillar(pillar: u64, factor: u8) -> u64 {
+    let mask = (1u64 << factor) - 1;
+    let mut reduced = 0;
+    for y in 0..64 / factor {
+        if (pillar >> (y * factor)) & mask != 0 {
+            reduced |= 1 << y;
+        }
+    }
+    reduced
+}
+
+#[cfg(test)]
+mod test {
+    use crate::voxel_storage::VoxelStorage;
+
+    use super::{downsample, reduce_pillar};
+
+    #[test]
+    fn pillar_reduction() {
+        assert_eq!(reduce_pillar(0, 2), 0);
+        assert_eq!(reduce_pillar(0b0110, 2), 0b11);
+        assert_eq!(reduce_pillar(1 << 63, 8), 1 << 7);
+        assert_eq!(reduce_pillar(u64::MAX, 4), 0xffff);
+    }
+
+    #[test]
+    fn merged_voxels() {
+        let mut storage = VoxelStorage::empty();
+        for x in 0..64 {
+            for z in 0..64 {
+                storage.set([x, 0, z]);
+            }
+        }
+        storage.set([13, 20, 50]);
+        for factor in [2u8, 4, 8] {
+            let reduced = downsample(&storage, factor);
+            let cells = 64 / factor as u64;
+            assert_eq!(reduced.count(), cells * cells + 1);
+            assert!(reduced.get([13 / factor, 20 / factor, 50 / factor]));
+            // nothing outside of the reduced grid
+            assert!(!reduced.get([cells as u8, 0, 0]));
+        }
+    }
+
+    #[test]
+    fn single_voxel_stays_closed() {
+        let mut storage = VoxelStorage::empty();
+        storage.set([5, 5, 5]);
+        let faces = downsample(&storage, 4).visible_faces();
+        assert_eq!(faces.total(), 6);
+        assert_eq!(faces.top, vec![[1, 1, 1]]);
+    }
+}
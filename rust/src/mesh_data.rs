@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+/// indexed triangle mesh in plain buffers, triangles are clockwise seen from outside.
+/// Normals have one entry per position, the other attributes one entry per position or none
+/// if the mesher does not produce them.
+#[derive(Default, Debug)]
+pub struct MeshData {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub colors: Vec<[f32; 4]>,
+    /// tangent in xyz, sign of the binormal in w
+    pub tangents: Vec<[f32; 4]>,
+    pub indices: Vec<i32>,
+}
+
+impl MeshData {
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    /// corner positions of every triangle, in index order
+    pub fn triangles(&self) -> impl Iterator<Item = [[f32; 3]; 3]> + '_ {
+        self.indices
+            .chunks(3)
+            .map(|t| [0, 1, 2].map(|i| self.positions[t[i] as usize]))
+    }
+}
+
+/// outward facing normal of a clockwise triangle, length is twice the area
+pub fn triangle_normal([a, b, c]: [[f32; 3]; 3]) -> [f32; 3] {
+    let e1 = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let e2 = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+    [
+        -(e1[1] * e2[2] - e1[2] * e2[1]),
+        -(e1[2] * e2[0] - e1[0] * e2[2]),
+        -(e1[0] * e2[1] - e1[1] * e2[0]),
+    ]
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Vertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+    pub tangent: [f32; 4],
+}
+
+impl Vertex {
+    pub fn new(position: [f32; 3], normal: [f32; 3]) -> Self {
+        Vertex {
+            position,
+            normal,
+            uv: [0.0; 2],
+            color: [1.0; 4],
+            tangent: [0.0; 4],
+        }
+    }
+
+    fn key(&self) -> [u32; 16] {
+        let mut key = [0; 16];
+        let values = self
+            .position
+            .iter()
+            .chain(self.normal.iter())
+            .chain(self.uv.iter())
+            .chain(self.color.iter())
+            .chain(self.tangent.iter());
+        for (k, v) in key.iter_mut().zip(values) {
+            *k = v.to_bits();
+        }
+        key
+    }
+}
+
+/// Collects triangles into a `MeshData`, vertices are shared if all their attributes match.
+#[derive(Default)]
+pub struct MeshBuilder {
+    data: MeshData,
+    lookup: HashMap<[u32; 16], i32>,
+}
+
+impl MeshBuilder {
+    pub fn vertex(&mut self, vertex: Vertex) -> i32 {
+        let data = &mut self.data;
+        *self.lookup.entry(vertex.key()).or_insert_with(|| {
+            data.positions.push(vertex.position);
+            data.normals.push(vertex.normal);
+            data.uvs.push(vertex.uv);
+            data.colors.push(vertex.color);
+            data.tangents.push(vertex.tangent);
+            data.positions.len() as i32 - 1
+        })
+    }
+
+    /// Two triangles of a quad whose corners go around the face in the order 0, 1, 3, 2, so 0 and
+    /// 3 are opposite corners. Without `reversed` the corners run clockwise seen from the side the
+    /// face points to, the front face winding of Godot, `reversed` takes corners running
+    /// counter-clockwise. The quad is split along the diagonal connecting the brighter pair of
+    /// corners, so colors like ambient occlusion are interpolated symmetrically.
+    pub fn quad(&mut self, corners: [Vertex; 4], reversed: bool) {
+        let brightness = corners.map(|c| c.color[0] + c.color[1] + c.color[2]);
+        let flip = brightness[0] + brightness[3] > brightness[1] + brightness[2];
+        let order = match (flip, reversed) {
+            (false, false) => [0, 1, 2, 2, 1, 3],
+            (false, true) => [0, 2, 1, 2, 3, 1],
+            (true, false) => [0, 1, 3, 0, 3, 2],
+            (true, true) => [0, 3, 1, 0, 2, 3],
+        };
+        let indices = corners.map(|c| self.vertex(c));
+        self.data.indices.extend(order.map(|o| indices[o]));
+    }
+
+    pub fn finish(self) -> MeshData {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MeshBuilder, Vertex};
+
+    #[test]
+    fn shares_equal_vertices() {
+        let mut builder = MeshBuilder::default();
+        let up = [0.0, 1.0, 0.0];
+        let corners = |x: f32| {
+            [[x, 0.0], [x, 1.0], [x + 1.0, 0.0], [x + 1.0, 1.0]]
+                .map(|[x, z]| Vertex::new([x, 0.0, z], up))
+        };
+        builder.quad(corners(0.0), false);
+        builder.quad(corners(1.0), false);
+        // same position, different normal
+        builder.vertex(Vertex::new([0.0, 0.0, 0.0], [0.0, -1.0, 0.0]));
+        let mesh = builder.finish();
+        assert_eq!(mesh.positions.len(), 7);
+        assert_eq!(mesh.colors.len(), 7);
+        assert_eq!(mesh.triangle_count(), 4);
+    }
+
+    #[test]
+    fn split_along_brighter_diagonal() {
+        let mut corners = [[0.0, 0.0], [0.0, 1.0], [1.0, 0.0], [1.0, 1.0]]
+            .map(|[x, z]| Vertex::new([x, 0.0, z], [0.0, 1.0, 0.0]));
+        corners[0].color = [0.4, 0.4, 0.4, 1.0];
+        let mut builder = MeshBuilder::default();
+        builder.quad(corners, false);
+        // the dark corner 0 is only part of one triangle
+        let mesh = builder.finish();
+        assert_eq!(mesh.indices, vec![0, 1, 2, 2, 1, 3]);
+        corners[0].color = [1.0; 4];
+        corners[1].color = [0.4, 0.4, 0.4, 1.0];
+        let mut builder = MeshBuilder::default();
+        builder.quad(corners, false);
+        assert_eq!(builder.finish().indices, vec![0, 1, 3, 0, 3, 2]);
+    }
+}
@@ -0,0 +1,406 @@
+use std::fmt::Write;
+
+use crate::blocky_mesh::{chunk_ground, chunk_water};
+use crate::mesh_data::MeshData;
+use crate::voxel_storage::VoxelWorld;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExportMaterial {
+    Ground,
+    Water,
+}
+
+impl ExportMaterial {
+    fn name(self) -> &'static str {
+        match self {
+            ExportMaterial::Ground => "ground",
+            ExportMaterial::Water => "water",
+        }
+    }
+
+    fn color(self) -> [f32; 4] {
+        match self {
+            ExportMaterial::Ground => [0.0, 0.2, 0.0, 1.0],
+            ExportMaterial::Water => [0.0, 0.0, 1.0, 0.5],
+        }
+    }
+}
+
+pub struct ExportMesh {
+    pub name: String,
+    pub translation: [f32; 3],
+    pub material: ExportMaterial,
+    pub mesh: MeshData,
+}
+
+/// ground and water mesh of every chunk, named like the chunk nodes in Godot
+pub fn chunk_meshes(world: &VoxelWorld, water_lowering: f32) -> Vec<ExportMesh> {
+    let mut coords: Vec<[i8; 2]> = world.ground.keys().cloned().collect();
+    coords.sort();
+    let mut meshes = Vec::new();
+    for coord in coords {
+        let translation = [coord[0] as f32 * 64.0, 0.0, coord[1] as f32 * 64.0];
+        meshes.push(ExportMesh {
+            name: format!("ground_{}_{}", coord[0], coord[1]),
+            translation,
+            material: ExportMaterial::Ground,
+            mesh: chunk_ground(world, coord),
+        });
+        meshes.push(ExportMesh {
+            name: format!("water_{}_{}", coord[0], coord[1]),
+            translation,
+            material: ExportMaterial::Water,
+            mesh: chunk_water(world, coord, water_lowering),
+        });
+    }
+    meshes
+}
+
+/// one mesh per material with the translations applied, attributes that are missing in any of
+/// the merged meshes are dropped
+pub fn merge(meshes: Vec<ExportMesh>) -> Vec<ExportMesh> {
+    let mut merged: Vec<ExportMesh> = Vec::new();
+    for part in meshes {
+        let target = match merged.iter_mut().find(|m| m.material == part.material) {
+            Some(target) => target,
+            None => {
+                merged.push(ExportMesh {
+                    name: part.material.name().to_string(),
+                    translation: [0.0; 3],
+                    material: part.material,
+                    mesh: MeshData::default(),
+                });
+                merged.last_mut().unwrap()
+            }
+        };
+        let mesh = &mut target.mesh;
+        let first = mesh.positions.is_empty() && mesh.indices.is_empty();
+        let base = mesh.positions.len() as i32;
+        let t = part.translation;
+        mesh.positions.extend(
+            part.mesh
+                .positions
+                .iter()
+                .map(|p| [p[0] + t[0], p[1] + t[1], p[2] + t[2]]),
+        );
+        mesh.normals.extend_from_slice(&part.mesh.normals);
+        mesh.indices
+            .extend(part.mesh.indices.iter().map(|i| i + base));
+        if first {
+            mesh.uvs = part.mesh.uvs;
+            mesh.colors = part.mesh.colors;
+            mesh.tangents = part.mesh.tangents;
+        } else {
+            merge_attribute(&mut mesh.uvs, part.mesh.uvs);
+            merge_attribute(&mut mesh.colors, part.mesh.colors);
+            merge_attribute(&mut mesh.tangents, part.mesh.tangents);
+        }
+    }
+    merged
+}
+
+fn merge_attribute<T>(target: &mut Vec<T>, part: Vec<T>) {
+    if target.is_empty() || part.is_empty() {
+        target.clear();
+    } else {
+        target.extend(part);
+    }
+}
+
+/// Wavefront OBJ with one object per mesh, translations are applied to the positions.
+/// OBJ faces are counter clockwise, so the winding is reversed.
+pub fn write_obj(meshes: &[ExportMesh]) -> String {
+    let mut obj = String::new();
+    let mut base = 1;
+    // water has no uvs, so texture coordinates are counted apart from the positions
+    let mut uv_base = 1;
+    for part in meshes.iter().filter(|m| !m.mesh.indices.is_empty()) {
+        let mesh = &part.mesh;
+        let t = part.translation;
+        writeln!(obj, "o {}", part.name).unwrap();
+        writeln!(obj, "usemtl {}", part.material.name()).unwrap();
+        for p in mesh.positions.iter() {
+            writeln!(obj, "v {} {} {}", p[0] + t[0], p[1] + t[1], p[2] + t[2]).unwrap();
+        }
+        for n in mesh.normals.iter() {
+            writeln!(obj, "vn {} {} {}", n[0], n[1], n[2]).unwrap();
+        }
+        for uv in mesh.uvs.iter() {
+            writeln!(obj, "vt {} {}", uv[0], uv[1]).unwrap();
+        }
+        let has_uvs = !mesh.uvs.is_empty();
+        for triangle in mesh.indices.chunks(3) {
+            obj.push('f');
+            for i in [triangle[0], triangle[2], triangle[1]] {
+                let (uv, i) = (i + uv_base, i + base);
+                if has_uvs {
+                    write!(obj, " {i}/{uv}/{i}").unwrap();
+                } else {
+                    write!(obj, " {i}//{i}").unwrap();
+                }
+            }
+            obj.push('\n');
+        }
+        base += mesh.positions.len() as i32;
+        uv_base += mesh.uvs.len() as i32;
+    }
+    obj
+}
+
+/// binary glTF 2.0 with one node per mesh and a material per `ExportMaterial`.
+/// glTF front faces are counter clockwise, so the winding is reversed.
+pub fn write_glb(meshes: &[ExportMesh]) -> Vec<u8> {
+    let (json, buffer) = gltf(meshes, None);
+    let mut json = json.into_bytes();
+    while json.len() % 4 != 0 {
+        json.push(b' ');
+    }
+    let mut glb = Vec::new();
+    glb.extend_from_slice(b"glTF");
+    glb.extend_from_slice(&2u32.to_le_bytes());
+    glb.extend_from_slice(&((12 + 8 + json.len() + 8 + buffer.len()) as u32).to_le_bytes());
+    glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"JSON");
+    glb.extend_from_slice(&json);
+    glb.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+    glb.extend_from_slice(b"BIN\0");
+    glb.extend_from_slice(&buffer);
+    glb
+}
+
+/// text glTF 2.0 with the buffer embedded as base64 data uri, otherwise like `write_glb`
+pub fn write_gltf(meshes: &[ExportMesh]) -> String {
+    let (_, buffer) = gltf(meshes, None);
+    let uri = format!("data:application/octet-stream;base64,{}", base64(&buffer));
+    gltf(meshes, Some(&uri)).0
+}
+
+/// json document and binary buffer, the buffer is referenced by `uri` or is the glb chunk
+fn gltf(meshes: &[ExportMesh], uri: Option<&str>) -> (String, Vec<u8>) {
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut json_meshes = Vec::new();
+    let mut nodes = Vec::new();
+    let mut view = |buffer: &mut Vec<u8>, data: &[u8], target: u32| {
+        views.push(format!(
+            r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{target}}}"#,
+            buffer.len(),
+            data.len()
+        ));
+        buffer.extend_from_slice(data);
+        views.len() - 1
+    };
+    let floats = |values: &mut dyn Iterator<Item = f32>| -> Vec<u8> {
+        values.flat_map(f32::to_le_bytes).collect()
+    };
+    for part in meshes.iter().filter(|m| !m.mesh.indices.is_empty()) {
+        let mesh = &part.mesh;
+        let count = mesh.positions.len();
+        let mut attributes = Vec::new();
+
+        let (mut min, mut max) = ([f32::MAX; 3], [f32::MIN; 3]);
+        for p in mesh.positions.iter() {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(p[axis]);
+                max[axis] = max[axis].max(p[axis]);
+            }
+        }
+        let data = floats(&mut mesh.positions.iter().flatten().copied());
+        let v = view(&mut buffer, &data, 34962);
+        accessors.push(format!(
+            r#"{{"bufferView":{v},"componentType":5126,"count":{count},"type":"VEC3","min":{min:?},"max":{max:?}}}"#
+        ));
+        attributes.push(format!(r#""POSITION":{}"#, accessors.len() - 1));
+
+        let channels: [(&str, &str, Vec<u8>); 3] = [
+            (
+                "NORMAL",
+                "VEC3",
+                floats(&mut mesh.normals.iter().flatten().copied()),
+            ),
+            (
+                "TEXCOORD_0",
+                "VEC2",
+                floats(&mut mesh.uvs.iter().flatten().copied()),
+            ),
+            (
+                "COLOR_0",
+                "VEC4",
+                floats(&mut mesh.colors.iter().flatten().copied()),
+            ),
+        ];
+        for (name, kind, data) in channels {
+            if data.is_empty() {
+                continue;
+            }
+            let v = view(&mut buffer, &data, 34962);
+            accessors.push(format!(
+                r#"{{"bufferView":{v},"componentType":5126,"count":{count},"type":"{kind}"}}"#
+            ));
+            attributes.push(format!(r#""{name}":{}"#, accessors.len() - 1));
+        }
+
+        let indices: Vec<u8> = mesh
+            .indices
+            .chunks(3)
+            .flat_map(|t| [t[0], t[2], t[1]])
+            .flat_map(|i| (i as u32).to_le_bytes())
+            .collect();
+        let v = view(&mut buffer, &indices, 34963);
+        accessors.push(format!(
+            r#"{{"bufferView":{v},"componentType":5125,"count":{},"type":"SCALAR"}}"#,
+            mesh.indices.len()
+        ));
+
+        json_meshes.push(format!(
+            r#"{{"name":"{}","primitives":[{{"attributes":{{{}}},"indices":{},"material":{}}}]}}"#,
+            part.name,
+            attributes.join(","),
+            accessors.len() - 1,
+            part.material as usize
+        ));
+        nodes.push(format!(
+            r#"{{"name":"{}","mesh":{},"translation":{:?}}}"#,
+            part.name,
+            json_meshes.len() - 1,
+            part.translation
+        ));
+    }
+    let materials: Vec<String> = [ExportMaterial::Ground, ExportMaterial::Water]
+        .iter()
+        .map(|m| {
+            let blend = if m.color()[3] < 1.0 { "BLEND" } else { "OPAQUE" };
+            format!(
+                r#"{{"name":"{}","pbrMetallicRoughness":{{"baseColorFactor":{:?},"metallicFactor":0.0}},"alphaMode":"{blend}"}}"#,
+                m.name(),
+                m.color()
+            )
+        })
+        .collect();
+    let uri = uri.map(|u| format!(r#","uri":"{u}""#)).unwrap_or_default();
+    let json = format!(
+        r#"{{"asset":{{"version":"2.0","generator":"godot_playground_water"}},"scene":0,"scenes":[{{"nodes":[{}]}}],"nodes":[{}],"meshes":[{}],"materials":[{}],"accessors":[{}],"bufferViews":[{}],"buffers":[{{"byteLength":{}{uri}}}]}}"#,
+        (0..nodes.len())
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join(","),
+        nodes.join(","),
+        json_meshes.join(","),
+        materials.join(","),
+        accessors.join(","),
+        views.join(","),
+        buffer.len()
+    );
+    (json, buffer)
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for group in data.chunks(3) {
+        let b = [0, 1, 2].map(|i| group.get(i).copied().unwrap_or(0) as u32);
+        let n = b[0] << 16 | b[1] << 8 | b[2];
+        for i in 0..4 {
+            if i <= group.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use crate::voxel_storage::VoxelWorld;
+
+    use super::{base64, chunk_meshes, merge, write_glb, write_gltf, write_obj};
+
+    fn world() -> VoxelWorld {
+        let mut world = VoxelWorld::empty(0..2, 0..1);
+        world.ground.get_mut(&[0, 0]).unwrap().set([1, 0, 1]);
+        world.ground.get_mut(&[1, 0]).unwrap().set([1, 0, 1]);
+        world.water.get_mut(&[1, 0]).unwrap().set([5, 0, 5]);
+        world
+    }
+
+    #[test]
+    fn obj_objects_and_winding() {
+        let meshes = chunk_meshes(&world(), 0.0);
+        assert_eq!(meshes.len(), 4);
+        let obj = write_obj(&meshes);
+        // the water of chunk 0 is empty and left out
+        assert_eq!(obj.lines().filter(|l| l.starts_with("o ")).count(), 3);
+        assert!(obj.contains("o ground_1_0"));
+        // vertices of the second chunk are translated
+        assert!(obj.contains("v 65 0 1"));
+        let faces = obj.lines().filter(|l| l.starts_with("f ")).count();
+        assert_eq!(faces, 6 * 2 + 6 * 2 + 5 * 2);
+
+        let merged = merge(meshes);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].mesh.triangle_count(), 24);
+        // water has no uvs, so the merged water has none either
+        assert!(merged[1].mesh.uvs.is_empty());
+        let merged_obj = write_obj(&merged);
+        assert_eq!(
+            merged_obj.lines().filter(|l| l.starts_with("o ")).count(),
+            2
+        );
+    }
+
+    #[test]
+    fn obj_uv_indices_after_water() {
+        // water without uvs in the first chunk, ground with uvs after it
+        let mut world = world();
+        world.water.get_mut(&[0, 0]).unwrap().set([5, 0, 5]);
+        let obj = write_obj(&chunk_meshes(&world, 0.0));
+        let count = |prefix: &str| obj.lines().filter(|l| l.starts_with(prefix)).count();
+        let (positions, uvs) = (count("v "), count("vt "));
+        for face in obj.lines().filter(|l| l.starts_with("f ")) {
+            for vertex in face.split(' ').skip(1) {
+                let indices: Vec<&str> = vertex.split('/').collect();
+                assert!(indices[0].parse::<usize>().unwrap() <= positions);
+                if !indices[1].is_empty() {
+                    assert!(indices[1].parse::<usize>().unwrap() <= uvs);
+                }
+            }
+        }
+        assert!(obj.contains("o ground_1_0"));
+        assert!(uvs > 0);
+    }
+
+    #[test]
+    fn glb_layout() {
+        let meshes = chunk_meshes(&world(), 0.1);
+        let glb = write_glb(&meshes);
+        assert_eq!(&glb[0..4], b"glTF");
+        assert_eq!(
+            u32::from_le_bytes(glb[8..12].try_into().unwrap()) as usize,
+            glb.len()
+        );
+        let json_length = u32::from_le_bytes(glb[12..16].try_into().unwrap()) as usize;
+        assert_eq!(&glb[16..20], b"JSON");
+        let json = std::str::from_utf8(&glb[20..20 + json_length]).unwrap();
+        assert!(json.contains(r#""name":"water_1_0""#));
+        assert!(!json.contains("water_0_0"));
+        let bin = &glb[20 + json_length..];
+        let bin_length = u32::from_le_bytes(bin[0..4].try_into().unwrap()) as usize;
+        assert_eq!(&bin[4..8], b"BIN\0");
+        assert_eq!(bin.len(), 8 + bin_length);
+        assert!(json.contains(&format!(r#""buffers":[{{"byteLength":{bin_length}}}]"#)));
+
+        let gltf = write_gltf(&meshes);
+        assert!(gltf.contains("data:application/octet-stream;base64,"));
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::HashMap;
+
+use crate::voxel_storage::VoxelWorld;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WaterMode {
+    /// cells covered by water are left out
+    Impassable,
+    /// cells covered by water go into `ChunkNavigation::water`
+    Costly,
+}
+
+pub struct NavSettings {
+    /// free cells required above the floor
+    pub agent_height: u8,
+    /// largest height difference between neighbouring cells that can be walked
+    pub step_height: u8,
+    pub water: WaterMode,
+}
+
+/// polygon soup in chunk local coordinates, polygons are clockwise seen from above
+#[derive(Default)]
+pub struct NavMeshData {
+    pub vertices: Vec<[f32; 3]>,
+    pub polygons: Vec<Vec<i32>>,
+    /// Start and end of bidirectional links between the floors of neighbouring cells of different
+    /// height, at the centres of the cells. A link belongs to the mesh of its lower cell.
+    pub links: Vec<[[f32; 3]; 2]>,
+    indices: HashMap<[i32; 3], i32>,
+}
+
+impl NavMeshData {
+    fn vertex(&mut self, p: [i32; 3]) -> i32 {
+        let vertices = &mut self.vertices;
+        *self.indices.entry(p).or_insert_with(|| {
+            vertices.push([p[0] as f32, p[1] as f32, p[2] as f32]);
+            vertices.len() as i32 - 1
+        })
+    }
+
+    fn polygon(&mut self, corners: [[i32; 3]; 4]) {
+        let polygon = corners.iter().map(|c| self.vertex(*c)).collect();
+        self.polygons.push(polygon);
+    }
+
+    fn link(&mut self, from: [i32; 3], to: [i32; 3]) {
+        let centre = |p: [i32; 3]| [p[0] as f32 + 0.5, p[1] as f32, p[2] as f32 + 0.5];
+        self.links.push([centre(from), centre(to)]);
+    }
+}
+
+pub struct ChunkNavigation {
+    pub land: NavMeshData,
+    pub water: NavMeshData,
+}
+
+/// Bit masks per pillar of the cells an agent can stand in: the cell below is ground and
+/// `agent_height` cells starting at the cell are free of ground. Cells above the world are free.
+/// Returns (dry, wet) where wet cells contain water.
+fn standing_cells(world: &VoxelWorld, p: [i32; 2], settings: &NavSettings) -> (u64, u64) {
+    let Some((chunk, local)) = world.locate([p[0], 0, p[1]]) else {
+        return (0, 0);
+    };
+    let ground = world.ground[&chunk].get_pillar([local[0], local[2]]);
+    let water = world.water[&chunk].get_pillar([local[0], local[2]]);
+    let free = !ground;
+    let mut stand = (ground << 1) & free;
+    for k in 1..settings.agent_height.clamp(1, 64) as u32 {
+        stand &= (free >> k) | !(u64::MAX >> k);
+    }
+    (stand & !water, stand & water)
+}
+
+/// height of the standing cell of `mask` closest to `y` that is reachable within `step_height`
+fn step_target(mask: u64, y: u32, step_height: u8) -> Option<u32> {
+    let reach = (y.saturating_sub(step_height as u32)..=(y + step_height as u32).min(63))
+        .filter(|h| *h != y && (mask >> h) & 1 == 1);
+    reach.min_by_key(|h| h.abs_diff(y))
+}
+
+pub fn bake_chunk(world: &VoxelWorld, chunk: [i8; 2], settings: &NavSettings) -> ChunkNavigation {
+    let mut land = NavMeshData::default();
+    let mut water = NavMeshData::default();
+    let origin = [chunk[0] as i32 * 64, chunk[1] as i32 * 64];
+    let passable = |p: [i32; 2]| {
+        let (dry, wet) = standing_cells(world, [origin[0] + p[0], origin[1] + p[1]], settings);
+        match settings.water {
+            WaterMode::Impassable => (dry, dry),
+            WaterMode::Costly => (dry, dry | wet),
+        }
+    };
+    for z in 0..64i32 {
+        for x in 0..64i32 {
+            let (dry, current) = passable([x, z]);
+            if current == 0 {
+                continue;
+            }
+            let (next_x_dry, next_x) = passable([x + 1, z]);
+            let (next_z_dry, next_z) = passable([x, z + 1]);
+            for y in 0..64i32 {
+                if (current >> y) & 1 == 0 {
+                    continue;
+                }
+                let target = if (dry >> y) & 1 == 1 {
+                    &mut land
+                } else {
+                    &mut water
+                };
+                target.polygon([[x, y, z], [x + 1, y, z], [x + 1, y, z + 1], [x, y, z + 1]]);
+
+                // cells of different height are linked from the cell with the smaller x or z
+                for (next, next_dry, [dx, dz]) in
+                    [(next_x, next_x_dry, [1, 0]), (next_z, next_z_dry, [0, 1])]
+                {
+                    if (next >> y) & 1 == 1 {
+                        continue;
+                    }
+                    let Some(h) = step_target(next, y as u32, settings.step_height) else {
+                        continue;
+                    };
+                    let h = h as i32;
+                    let lower_is_dry = if h < y {
+                        (next_dry >> h) & 1 == 1
+                    } else {
+                        (dry >> y) & 1 == 1
+                    };
+                    let target = if lower_is_dry { &mut land } else { &mut water };
+                    target.link([x, y, z], [x + dx, h, z + dz]);
+                }
+            }
+        }
+    }
+    ChunkNavigation { land, water }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::Reverse;
+    use std::collections::{BinaryHeap, HashMap};
+
+    use crate::voxel_storage::VoxelWorld;
+
+    use super::{bake_chunk, ChunkNavigation, NavSettings, WaterMode};
+
+    fn settings(water: WaterMode) -> NavSettings {
+        NavSettings {
+            agent_height: 2,
+            step_height: 1,
+            water,
+        }
+    }
+
+    fn floor_world() -> VoxelWorld {
+        let mut world = VoxelWorld::empty(0..1, 0..1);
+        let ground = world.ground.get_mut(&[0, 0]).unwrap();
+        for x in 0..64 {
+            for z in 0..64 {
+                ground.set([x, 0, z]);
+            }
+        }
+        world
+    }
+
+    #[test]
+    fn flat_floor() {
+        let world = floor_world();
+        let nav = bake_chunk(&world, [0, 0], &settings(WaterMode::Impassable));
+        assert_eq!(nav.land.polygons.len(), 64 * 64);
+        assert_eq!(nav.land.vertices.len(), 65 * 65);
+        assert!(nav.land.vertices.iter().all(|v| v[1] == 1.0));
+        assert!(nav.water.polygons.is_empty());
+    }
+
+    /// Cost of the cheapest route between the floors of two cells, queried like a navigation
+    /// map: floors connect through shared edges and through links, entering a floor or taking a
+    /// link costs the travel cost of its mesh.
+    fn route_cost(
+        nav: &ChunkNavigation,
+        water_cost: u32,
+        from: [i32; 3],
+        to: [i32; 3],
+    ) -> Option<u32> {
+        let mut floors: HashMap<[i32; 3], u32> = HashMap::new();
+        let mut links: Vec<([i32; 3], [i32; 3], u32)> = Vec::new();
+        for (mesh, cost) in [(&nav.land, 1), (&nav.water, water_cost)] {
+            for polygon in mesh.polygons.iter() {
+                // the first corner of a floor is its lowest one
+                floors.insert(mesh.vertices[polygon[0] as usize].map(|c| c as i32), cost);
+            }
+            for [a, b] in mesh.links.iter() {
+                let cell = |p: [f32; 3]| p.map(|c| c.floor() as i32);
+                links.push((cell(*a), cell(*b), cost));
+            }
+        }
+        let mut best: HashMap<[i32; 3], u32> = HashMap::new();
+        let mut open = BinaryHeap::new();
+        open.push(Reverse((0, from)));
+        while let Some(Reverse((cost, cell))) = open.pop() {
+            if cell == to {
+                return Some(cost);
+            }
+            let sides = [[1, 0], [-1, 0], [0, 1], [0, -1]]
+                .map(|[dx, dz]| [cell[0] + dx, cell[1], cell[2] + dz])
+                .into_iter()
+                .filter_map(|n| floors.get(&n).map(|c| (n, *c)));
+            let linked = links.iter().filter_map(|(a, b, c)| {
+                (*a == cell)
+                    .then_some((*b, *c))
+                    .or((*b == cell).then_some((*a, *c)))
+            });
+            for (next, step) in sides.chain(linked) {
+                if best.get(&next).map(|b| cost + step < *b).unwrap_or(true) {
+                    best.insert(next, cost + step);
+                    open.push(Reverse((cost + step, next)));
+                }
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn headroom_and_steps() {
+        let mut world = floor_world();
+        let ground = world.ground.get_mut(&[0, 0]).unwrap();
+        // low ceiling over one cell
+        ground.set([10, 2, 10]);
+        // a step of one and a wall of two
+        ground.set([20, 1, 20]);
+        ground.set([30, 1, 30]);
+        ground.set([30, 2, 30]);
+        let nav = bake_chunk(&world, [0, 0], &settings(WaterMode::Impassable));
+        // each of the three blocks moves a floor cell up by its height, only the step is linked
+        // to its four neighbours, the ceiling and the wall are too high
+        assert_eq!(nav.land.polygons.len(), 64 * 64);
+        assert_eq!(nav.land.links.len(), 4);
+        assert_eq!(route_cost(&nav, 1, [18, 1, 20], [20, 2, 20]), Some(2));
+        assert_eq!(route_cost(&nav, 1, [18, 1, 30], [30, 3, 30]), None);
+    }
+
+    #[test]
+    fn wet_steps_cost_like_water() {
+        let mut world = floor_world();
+        // a step up inside of a pond, walled in on the other three sides
+        let ground = world.ground.get_mut(&[0, 0]).unwrap();
+        ground.set([6, 1, 5]);
+        for [x, z] in [[6, 4], [6, 6], [7, 5]] {
+            for y in 1..4 {
+                ground.set([x, y, z]);
+            }
+        }
+        let water = world.water.get_mut(&[0, 0]).unwrap();
+        water.set([5, 1, 5]);
+        water.set([6, 2, 5]);
+        let nav = bake_chunk(&world, [0, 0], &settings(WaterMode::Costly));
+        assert_eq!(nav.water.links, vec![[[5.5, 1.0, 5.5], [6.5, 2.0, 5.5]]]);
+        // into the pond and up the step, both at the cost of water
+        assert_eq!(route_cost(&nav, 4, [4, 1, 5], [6, 2, 5]), Some(8));
+    }
+
+    #[test]
+    fn water_modes() {
+        let mut world = floor_world();
+        world.water.get_mut(&[0, 0]).unwrap().set([5, 1, 5]);
+        let impassable = bake_chunk(&world, [0, 0], &settings(WaterMode::Impassable));
+        assert_eq!(impassable.land.polygons.len(), 64 * 64 - 1);
+        assert!(impassable.water.polygons.is_empty());
+        let costly = bake_chunk(&world, [0, 0], &settings(WaterMode::Costly));
+        assert_eq!(costly.land.polygons.len(), 64 * 64 - 1);
+        assert_eq!(costly.water.polygons.len(), 1);
+    }
+}
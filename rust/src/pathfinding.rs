@@ -0,0 +1,345 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::voxel_storage::VoxelWorld;
+
+pub struct PathSettings {
+    /// free cells an agent needs, starting at the cell it stands in
+    pub agent_height: u8,
+    /// cells that can be stepped up to a neighbouring pillar
+    pub max_step: u8,
+    /// cells that can be dropped down to a neighbouring pillar
+    pub max_drop: u8,
+    /// water cells can be moved through in all directions, without ground below
+    pub swim: bool,
+    /// search gives up after expanding this many cells
+    pub max_visited: usize,
+}
+
+impl Default for PathSettings {
+    fn default() -> Self {
+        PathSettings {
+            agent_height: 2,
+            max_step: 1,
+            max_drop: 3,
+            swim: true,
+            max_visited: 100_000,
+        }
+    }
+}
+
+// costs in tenths of a cell
+const WALK: u32 = 10;
+const CLIMB: u32 = 15;
+const DROP: u32 = 12;
+const SWIM: u32 = 20;
+
+/// Finds a path of cells an agent can occupy from `start` to `goal` with A*.
+/// Cells are voxel positions in world space, the agent stands in the cell above the ground.
+/// The path includes start and goal.
+pub fn find_path(
+    world: &VoxelWorld,
+    start: [i32; 3],
+    goal: [i32; 3],
+    settings: &PathSettings,
+) -> Option<Vec<[i32; 3]>> {
+    let grid = Grid { world, settings };
+    if !grid.can_occupy(start) || !grid.can_occupy(goal) {
+        return None;
+    }
+    let mut open = BinaryHeap::new();
+    let mut came_from: HashMap<[i32; 3], [i32; 3]> = HashMap::new();
+    let mut cost: HashMap<[i32; 3], u32> = HashMap::new();
+    cost.insert(start, 0);
+    open.push(Reverse((heuristic(start, goal), start)));
+    let mut visited = 0;
+    while let Some(Reverse((f, current))) = open.pop() {
+        let current_cost = cost[&current];
+        // a cheaper entry for this node was pushed after this one
+        if f > current_cost + heuristic(current, goal) {
+            continue;
+        }
+        if current == goal {
+            let mut path = vec![current];
+            let mut p = current;
+            while let Some(previous) = came_from.get(&p) {
+                path.push(*previous);
+                p = *previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        visited += 1;
+        if visited > settings.max_visited {
+            return None;
+        }
+        for (next, step_cost) in grid.neighbours(current) {
+            let next_cost = current_cost + step_cost;
+            if cost.get(&next).map(|c| next_cost < *c).unwrap_or(true) {
+                cost.insert(next, next_cost);
+                came_from.insert(next, current);
+                open.push(Reverse((next_cost + heuristic(next, goal), next)));
+            }
+        }
+    }
+    None
+}
+
+/// moves the position down onto the first cell an agent can occupy, at most `max_drop` cells
+pub fn snap_down(world: &VoxelWorld, p: [i32; 3], settings: &PathSettings) -> Option<[i32; 3]> {
+    let grid = Grid { world, settings };
+    (0..=settings.max_drop as i32)
+        .map(|d| [p[0], p[1] - d, p[2]])
+        .find(|c| grid.can_occupy(*c))
+}
+
+/// Every move but swimming up or down changes the pillar and costs at least `WALK`, and drops
+/// and climbs cover several cells of height at once, so only the horizontal distance keeps the
+/// estimate from exceeding the real cost.
+fn heuristic(a: [i32; 3], b: [i32; 3]) -> u32 {
+    (a[0].abs_diff(b[0]) + a[2].abs_diff(b[2])) * WALK
+}
+
+struct Grid<'a> {
+    world: &'a VoxelWorld,
+    settings: &'a PathSettings,
+}
+
+impl Grid<'_> {
+    /// not ground, cells above the world are free, cells below or beside it are not
+    fn is_free(&self, p: [i32; 3]) -> bool {
+        if p[1] >= 64 {
+            return self.world.locate([p[0], 63, p[2]]).is_some();
+        }
+        self.world.locate(p).is_some() && !self.world.is_ground(p)
+    }
+
+    fn has_room(&self, p: [i32; 3]) -> bool {
+        (0..self.settings.agent_height.max(1) as i32).all(|h| self.is_free([p[0], p[1] + h, p[2]]))
+    }
+
+    fn is_swimming(&self, p: [i32; 3]) -> bool {
+        self.settings.swim && self.world.is_water(p)
+    }
+
+    fn can_occupy(&self, p: [i32; 3]) -> bool {
+        self.has_room(p) && (self.world.is_ground([p[0], p[1] - 1, p[2]]) || self.is_swimming(p))
+    }
+
+    fn neighbours(&self, p: [i32; 3]) -> Vec<([i32; 3], u32)> {
+        let mut r = Vec::new();
+        let swimming = self.is_swimming(p);
+        for [dx, dz] in [[1, 0], [-1, 0], [0, 1], [0, -1]] {
+            let side = [p[0] + dx, p[1], p[2] + dz];
+            if self.can_occupy(side) {
+                r.push((side, if swimming { SWIM } else { WALK }));
+                continue;
+            }
+            if self.has_room(side) {
+                // walk off an edge and fall onto the first floor below
+                for d in 1..=self.settings.max_drop as i32 {
+                    let below = [side[0], side[1] - d, side[2]];
+                    if !self.is_free(below) {
+                        break;
+                    }
+                    if self.can_occupy(below) {
+                        r.push((below, DROP));
+                        break;
+                    }
+                }
+            } else {
+                // climb onto a higher neighbour, the agent needs room above itself while climbing
+                for up in 1..=self.settings.max_step as i32 {
+                    if !self.is_free([
+                        p[0],
+                        p[1] + self.settings.agent_height as i32 + up - 1,
+                        p[2],
+                    ]) {
+                        break;
+                    }
+                    let above = [side[0], side[1] + up, side[2]];
+                    if self.can_occupy(above) {
+                        r.push((above, CLIMB));
+                        break;
+                    }
+                }
+            }
+        }
+        if swimming {
+            for dy in [1, -1] {
+                let vertical = [p[0], p[1] + dy, p[2]];
+                if self.can_occupy(vertical) {
+                    r.push((vertical, SWIM));
+                }
+            }
+        }
+        r
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::voxel_storage::VoxelWorld;
+
+    use super::{find_path, snap_down, Grid, PathSettings};
+
+    fn floor_world() -> VoxelWorld {
+        let mut world = VoxelWorld::empty(0..2, 0..1);
+        for ground in world.ground.values_mut() {
+            for x in 0..64 {
+                for z in 0..64 {
+                    ground.set([x, 0, z]);
+                }
+            }
+        }
+        world
+    }
+
+    fn set_ground(world: &mut VoxelWorld, p: [i32; 3]) {
+        let (chunk, local) = world.locate(p).unwrap();
+        world.ground.get_mut(&chunk).unwrap().set(local);
+    }
+
+    #[test]
+    fn straight_line_across_chunks() {
+        let world = floor_world();
+        let path = find_path(&world, [60, 1, 5], [70, 1, 5], &PathSettings::default()).unwrap();
+        assert_eq!(path.len(), 11);
+        assert_eq!(path.first(), Some(&[60, 1, 5]));
+        assert_eq!(path.last(), Some(&[70, 1, 5]));
+        assert!(path
+            .windows(2)
+            .all(|w| (w[0][0] - w[1][0]).abs() + (w[0][2] - w[1][2]).abs() == 1));
+    }
+
+    #[test]
+    fn climb_and_drop() {
+        let mut world = floor_world();
+        // a wall across the whole chunk, one cell high, with a two cell high plateau behind it
+        for z in 0..64 {
+            set_ground(&mut world, [10, 1, z]);
+            for x in 11..20 {
+                set_ground(&mut world, [x, 1, z]);
+                set_ground(&mut world, [x, 2, z]);
+            }
+        }
+        let path = find_path(&world, [5, 1, 5], [25, 1, 5], &PathSettings::default()).unwrap();
+        assert!(path.contains(&[10, 2, 5]));
+        assert!(path.contains(&[15, 3, 5]));
+        assert!(path.contains(&[20, 1, 5]));
+
+        let no_climbing = PathSettings {
+            max_step: 0,
+            ..PathSettings::default()
+        };
+        assert_eq!(find_path(&world, [5, 1, 5], [25, 1, 5], &no_climbing), None);
+        let short_drop = PathSettings {
+            max_drop: 1,
+            ..PathSettings::default()
+        };
+        // the way back down from the plateau is too deep
+        assert_eq!(find_path(&world, [15, 3, 5], [25, 1, 5], &short_drop), None);
+    }
+
+    #[test]
+    fn tunnel_and_swimming() {
+        let mut world = floor_world();
+        // solid hill with a dug tunnel of height 2
+        for x in 10..20 {
+            for z in 0..64 {
+                for y in 1..10 {
+                    if !(z == 30 && y < 3) {
+                        set_ground(&mut world, [x, y, z]);
+                    }
+                }
+            }
+        }
+        let path = find_path(&world, [5, 1, 5], [25, 1, 5], &PathSettings::default()).unwrap();
+        assert!(path.contains(&[15, 1, 30]));
+
+        // a moat on the other side blocks everyone but swimmers
+        for z in 0..64 {
+            for x in 30..33 {
+                let (chunk, local) = world.locate([x, 0, z]).unwrap();
+                world.ground.get_mut(&chunk).unwrap().unset(local);
+                world.water.get_mut(&chunk).unwrap().set(local);
+                world
+                    .water
+                    .get_mut(&chunk)
+                    .unwrap()
+                    .set([local[0], 1, local[2]]);
+            }
+        }
+        let swim = find_path(&world, [25, 1, 5], [40, 1, 5], &PathSettings::default()).unwrap();
+        assert!(swim.iter().any(|p| world.is_water(*p)));
+        let walk_only = PathSettings {
+            swim: false,
+            ..PathSettings::default()
+        };
+        assert_eq!(find_path(&world, [25, 1, 5], [40, 1, 5], &walk_only), None);
+    }
+
+    /// cost of the path, None if it contains a step that is not a move of the grid
+    fn path_cost(world: &VoxelWorld, path: &[[i32; 3]], settings: &PathSettings) -> Option<u32> {
+        let grid = Grid { world, settings };
+        path.windows(2)
+            .map(|w| {
+                grid.neighbours(w[0])
+                    .into_iter()
+                    .find(|(n, _)| *n == w[1])
+                    .map(|(_, c)| c)
+            })
+            .sum()
+    }
+
+    #[test]
+    fn drops_down_the_ledge_on_the_cheapest_path() {
+        let mut world = floor_world();
+        // a plateau three cells high, the goal lies beyond its edge at z = 12
+        for x in 1..24 {
+            for z in 0..12 {
+                for y in 1..4 {
+                    set_ground(&mut world, [x, y, z]);
+                }
+            }
+        }
+        let settings = PathSettings::default();
+        let path = find_path(&world, [1, 4, 1], [20, 1, 20], &settings).unwrap();
+        // 38 pillars, one of them a drop
+        assert_eq!(path_cost(&world, &path, &settings), Some(37 * 10 + 12));
+    }
+
+    #[test]
+    fn stale_entries_are_not_expanded() {
+        // stripes of ponds and land, swimming costs more than walking so cells are often
+        // reached more cheaply after they were queued
+        let mut world = VoxelWorld::empty(0..2, 0..1);
+        for (chunk, ground) in world.ground.iter_mut() {
+            let water = world.water.get_mut(chunk).unwrap();
+            for x in 0..64 {
+                for z in 0..64 {
+                    if (x as i32 / 7 + z as i32 / 5) % 2 == 0 {
+                        ground.set_pillar([x, z], 0b1);
+                        water.set_pillar([x, z], 0b1110);
+                    } else {
+                        ground.set_pillar([x, z], 0b1111);
+                    }
+                }
+            }
+        }
+        // needs 2828 expansions if stale entries are expanded again
+        let settings = PathSettings {
+            max_visited: 2500,
+            ..PathSettings::default()
+        };
+        assert!(find_path(&world, [60, 1, 1], [70, 1, 60], &settings).is_some());
+    }
+
+    #[test]
+    fn snap_to_floor() {
+        let world = floor_world();
+        let settings = PathSettings::default();
+        assert_eq!(snap_down(&world, [3, 3, 3], &settings), Some([3, 1, 3]));
+        assert_eq!(snap_down(&world, [3, 10, 3], &settings), None);
+    }
+}
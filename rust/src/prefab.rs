@@ -0,0 +1,498 @@
+use crate::voxel_storage::VoxelWorld;
+
+/// Small voxel structure stamped onto the terrain. Like `VoxelStorage` it keeps one pillar per
+/// column, bit y is the cell y above the base of the prefab.
+pub struct Prefab {
+    /// footprint in pillars along x and z
+    pub size: [u8; 2],
+    /// pillars in rows along x
+    pub ground: Vec<u64>,
+    pub water: Vec<u64>,
+}
+
+impl Prefab {
+    pub fn empty(size: [u8; 2]) -> Prefab {
+        let pillars = size[0] as usize * size[1] as usize;
+        Prefab {
+            size,
+            ground: vec![0; pillars],
+            water: vec![0; pillars],
+        }
+    }
+
+    fn index(&self, x: u8, z: u8) -> usize {
+        x as usize + z as usize * self.size[0] as usize
+    }
+
+    pub fn set_ground(&mut self, p: [u8; 3]) {
+        let i = self.index(p[0], p[2]);
+        self.ground[i] |= 1 << p[1];
+        self.water[i] &= !(1 << p[1]);
+    }
+
+    pub fn set_water(&mut self, p: [u8; 3]) {
+        let i = self.index(p[0], p[2]);
+        self.water[i] |= 1 << p[1];
+        self.ground[i] &= !(1 << p[1]);
+    }
+
+    /// ground in the box between both corners, including them
+    pub fn fill_ground(&mut self, from: [u8; 3], to: [u8; 3]) {
+        for x in from[0]..=to[0] {
+            for y in from[1]..=to[1] {
+                for z in from[2]..=to[2] {
+                    self.set_ground([x, y, z]);
+                }
+            }
+        }
+    }
+
+    /// trunk below a crown of leaves
+    pub fn tree() -> Prefab {
+        let mut tree = Prefab::empty([5, 5]);
+        tree.fill_ground([2, 0, 2], [2, 4, 2]);
+        tree.fill_ground([0, 4, 1], [4, 5, 3]);
+        tree.fill_ground([1, 4, 0], [3, 5, 4]);
+        tree.fill_ground([1, 6, 1], [3, 6, 3]);
+        tree
+    }
+
+    /// broken walls on a floor around a well of water
+    pub fn ruin() -> Prefab {
+        let mut ruin = Prefab::empty([9, 9]);
+        ruin.fill_ground([0, 0, 0], [8, 0, 8]);
+        for i in 0..9u8 {
+            // the walls crumble to different heights
+            for (x, z) in [(i, 0), (i, 8), (0, i), (8, i)] {
+                let height = (x * 3 + z * 5) % 4;
+                ruin.fill_ground([x, 1, z], [x, height, z]);
+            }
+        }
+        ruin.fill_ground([3, 1, 3], [5, 1, 5]);
+        ruin.set_water([4, 1, 4]);
+        ruin
+    }
+
+    /// deck with railings along x, long enough to cross a small river
+    pub fn bridge() -> Prefab {
+        let mut bridge = Prefab::empty([13, 3]);
+        bridge.fill_ground([0, 0, 0], [12, 0, 2]);
+        bridge.fill_ground([0, 1, 0], [12, 1, 0]);
+        bridge.fill_ground([0, 1, 2], [12, 1, 2]);
+        bridge
+    }
+}
+
+pub struct PrefabPlacement {
+    pub prefab: Prefab,
+    /// The world is split into squares of `spacing` pillars, each of which gets the prefab
+    /// with `chance` at the first position, in a random order, that satisfies the rules.
+    pub spacing: u8,
+    pub chance: f64,
+    /// largest difference of the ground heights below the footprint
+    pub max_slope: Option<u8>,
+    /// water has to lie within this many pillars of the footprint
+    pub near_water: Option<u8>,
+    /// Water has to cover the middle of the footprint and dry ground both ends along x.
+    /// Otherwise the whole footprint has to be dry.
+    pub over_water: bool,
+    /// cells the prefab is lowered into the ground
+    pub sink: u8,
+}
+
+pub struct PrefabSettings {
+    pub seed: u32,
+    /// placed in order, later prefabs do not overlap earlier ones
+    pub placements: Vec<PrefabPlacement>,
+}
+
+impl Default for PrefabSettings {
+    fn default() -> Self {
+        PrefabSettings {
+            seed: 0,
+            placements: vec![
+                PrefabPlacement {
+                    prefab: Prefab::bridge(),
+                    spacing: 32,
+                    chance: 1.0,
+                    max_slope: None,
+                    near_water: None,
+                    over_water: true,
+                    sink: 0,
+                },
+                PrefabPlacement {
+                    prefab: Prefab::ruin(),
+                    spacing: 64,
+                    chance: 0.5,
+                    max_slope: Some(2),
+                    near_water: None,
+                    over_water: false,
+                    sink: 1,
+                },
+                PrefabPlacement {
+                    prefab: Prefab::tree(),
+                    spacing: 12,
+                    chance: 0.6,
+                    max_slope: Some(1),
+                    near_water: Some(16),
+                    over_water: false,
+                    sink: 1,
+                },
+            ],
+        }
+    }
+}
+
+/// a prefab stamped onto the world, `origin` is the lowest corner of its footprint
+pub struct Placed {
+    pub placement: usize,
+    pub origin: [i32; 3],
+}
+
+/// Stamps the prefabs of all placements onto the world, see `PrefabPlacement` for the rules.
+/// Prefabs rest on the highest ground or water below their footprint.
+pub fn place_prefabs(world: &mut VoxelWorld, settings: &PrefabSettings) -> Vec<Placed> {
+    let min = [world.xs.start as i32 * 64, world.zs.start as i32 * 64];
+    let max = [world.xs.end as i32 * 64, world.zs.end as i32 * 64];
+    let mut placed: Vec<Placed> = Vec::new();
+    let mut occupied: Vec<[i32; 4]> = Vec::new();
+    let water = WaterMap::new(world);
+    for (k, placement) in settings.placements.iter().enumerate() {
+        let size = placement.prefab.size.map(|s| s as i32);
+        let spacing = placement.spacing.max(1) as i32;
+        for gz in (min[1]..max[1]).step_by(spacing as usize) {
+            for gx in (min[0]..max[0]).step_by(spacing as usize) {
+                let key = hash((settings.seed as u64) << 32 ^ (k as u64) << 24);
+                let h = hash(hash(key ^ gx as u64) ^ gz as u64);
+                if (h >> 11) as f64 / (1u64 << 53) as f64 >= placement.chance {
+                    continue;
+                }
+                let positions = (spacing * spacing) as u64;
+                let start = hash(h) % positions;
+                for t in 0..positions {
+                    let offset = ((start + t) % positions) as i32;
+                    let corner = [gx + offset % spacing, gz + offset / spacing];
+                    let rect = [
+                        corner[0],
+                        corner[1],
+                        corner[0] + size[0],
+                        corner[1] + size[1],
+                    ];
+                    if rect[2] > max[0] || rect[3] > max[1] {
+                        continue;
+                    }
+                    // one pillar of space between prefabs
+                    if occupied.iter().any(|o| {
+                        rect[0] <= o[2] && o[0] <= rect[2] && rect[1] <= o[3] && o[1] <= rect[3]
+                    }) {
+                        continue;
+                    }
+                    if let Some(base) = fits(world, &water, placement, corner) {
+                        let origin = [corner[0], base, corner[1]];
+                        stamp(world, &placement.prefab, origin);
+                        occupied.push(rect);
+                        placed.push(Placed {
+                            placement: k,
+                            origin,
+                        });
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    placed
+}
+
+/// the height the prefab is based at, if the position satisfies the rules of the placement
+fn fits(
+    world: &VoxelWorld,
+    water_map: &WaterMap,
+    placement: &PrefabPlacement,
+    corner: [i32; 2],
+) -> Option<i32> {
+    let size = placement.prefab.size.map(|s| s as i32);
+    let mut ground_heights = Vec::with_capacity((size[0] * size[1]) as usize);
+    let mut top = 0;
+    for z in 0..size[1] {
+        for x in 0..size[0] {
+            let (ground, water) = pillars(world, [corner[0] + x, corner[1] + z])?;
+            let wet = water.checked_shr(height(ground) as u32).unwrap_or(0) != 0;
+            if placement.over_water {
+                let middle = x == size[0] / 2 && z == size[1] / 2;
+                let end = (x == 0 || x == size[0] - 1) && z == size[1] / 2;
+                if (middle && !wet) || (end && wet) {
+                    return None;
+                }
+            } else if wet {
+                return None;
+            }
+            ground_heights.push(height(ground));
+            top = top.max(height(ground | water));
+        }
+    }
+    if let Some(slope) = placement.max_slope {
+        let lowest = ground_heights.iter().min()?;
+        let highest = ground_heights.iter().max()?;
+        if highest - lowest > slope as i32 {
+            return None;
+        }
+    }
+    if let Some(distance) = placement.near_water {
+        let d = distance as i32;
+        let from = [corner[0] - d, corner[1] - d];
+        let to = [corner[0] + size[0] + d, corner[1] + size[1] + d];
+        if water_map.count(from, to) == 0 {
+            return None;
+        }
+    }
+    Some(top - placement.sink as i32)
+}
+
+/// Sets the cells of the prefab with its lowest corner at `origin`, also across chunk borders.
+/// Ground cells replace water and water cells replace ground, cells outside of the world are
+/// left out.
+pub fn stamp(world: &mut VoxelWorld, prefab: &Prefab, origin: [i32; 3]) {
+    for z in 0..prefab.size[1] {
+        for x in 0..prefab.size[0] {
+            let i = prefab.index(x, z);
+            for y in 0..64 {
+                let (is_ground, is_water) = (
+                    prefab.ground[i] & (1 << y) != 0,
+                    prefab.water[i] & (1 << y) != 0,
+                );
+                if !is_ground && !is_water {
+                    continue;
+                }
+                let p = [origin[0] + x as i32, origin[1] + y, origin[2] + z as i32];
+                let Some((chunk, local)) = world.locate(p) else {
+                    continue;
+                };
+                let (set, unset) = if is_ground {
+                    (&mut world.ground, &mut world.water)
+                } else {
+                    (&mut world.water, &mut world.ground)
+                };
+                set.get_mut(&chunk).unwrap().set(local);
+                unset.get_mut(&chunk).unwrap().unset(local);
+            }
+        }
+    }
+}
+
+/// Summed area table of the pillars with water above their ground, so the water around a footprint
+/// is counted in constant time. Built once before the prefabs are placed.
+struct WaterMap {
+    min: [i32; 2],
+    width: usize,
+    depth: usize,
+    sums: Vec<u32>,
+}
+
+impl WaterMap {
+    fn new(world: &VoxelWorld) -> WaterMap {
+        let min = [world.xs.start as i32 * 64, world.zs.start as i32 * 64];
+        let width = world.xs.len() * 64;
+        let depth = world.zs.len() * 64;
+        let mut sums = vec![0; (width + 1) * (depth + 1)];
+        for z in 0..depth {
+            for x in 0..width {
+                let p = [min[0] + x as i32, min[1] + z as i32];
+                // flooded caves below the surface don't count
+                let wet = pillars(world, p).is_some_and(|(ground, water)| {
+                    water.checked_shr(height(ground) as u32).unwrap_or(0) != 0
+                }) as u32;
+                let i = x + 1 + (z + 1) * (width + 1);
+                sums[i] = wet + sums[i - 1] + sums[i - width - 1] - sums[i - width - 2];
+            }
+        }
+        WaterMap {
+            min,
+            width,
+            depth,
+            sums,
+        }
+    }
+
+    /// pillars with surface water between `from` and `to`, excluding `to`, clamped to the world
+    fn count(&self, from: [i32; 2], to: [i32; 2]) -> u32 {
+        let [x0, x1] = [from[0], to[0]].map(|x| (x - self.min[0]).clamp(0, self.width as i32));
+        let [z0, z1] = [from[1], to[1]].map(|z| (z - self.min[1]).clamp(0, self.depth as i32));
+        let at = |x: i32, z: i32| self.sums[x as usize + z as usize * (self.width + 1)];
+        at(x1, z1) + at(x0, z0) - at(x0, z1) - at(x1, z0)
+    }
+}
+
+/// ground and water pillar at the position, None outside of the world
+fn pillars(world: &VoxelWorld, p: [i32; 2]) -> Option<(u64, u64)> {
+    let (chunk, local) = world.locate([p[0], 0, p[1]])?;
+    let local = [local[0], local[2]];
+    Some((
+        world.ground[&chunk].get_pillar(local),
+        world.water[&chunk].get_pillar(local),
+    ))
+}
+
+/// first cell above the highest set cell of the pillar
+fn height(pillar: u64) -> i32 {
+    64 - pillar.leading_zeros() as i32
+}
+
+/// splitmix64, spreads the prefabs evenly over the sites
+fn hash(v: u64) -> u64 {
+    let mut z = v.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::hydrology::RiverSettings;
+    use crate::voxel_storage::{low_bits, GenSettings, VoxelWorld};
+
+    use super::{height, place_prefabs, stamp, Prefab, PrefabSettings};
+
+    /// flat ground up to height 10 with a channel of water at 30 <= x < 37 along z
+    fn channel() -> VoxelWorld {
+        let mut world = VoxelWorld::empty(0..2, 0..1);
+        for cx in 0..2 {
+            let ground = world.ground.get_mut(&[cx, 0]).unwrap();
+            let water = world.water.get_mut(&[cx, 0]).unwrap();
+            for x in 0..64 {
+                for z in 0..64 {
+                    let wx = cx as i32 * 64 + x as i32;
+                    if (30..37).contains(&wx) {
+                        ground.set_pillar([x, z], low_bits(6));
+                        water.set_pillar([x, z], low_bits(10) & !low_bits(6));
+                    } else {
+                        ground.set_pillar([x, z], low_bits(10));
+                    }
+                }
+            }
+        }
+        world
+    }
+
+    #[test]
+    fn stamps_across_chunk_borders() {
+        let mut world = channel();
+        stamp(&mut world, &Prefab::ruin(), [60, 10, 20]);
+        // floor on both sides of the border between chunk 0 and 1
+        assert!(world.is_ground([60, 10, 20]));
+        assert!(world.is_ground([68, 10, 28]));
+        assert!(world.is_water([64, 11, 24]));
+        // cells outside of the world are left out
+        stamp(&mut world, &Prefab::tree(), [126, 55, -2]);
+        assert!(world.is_ground([127, 59, 0]));
+    }
+
+    #[test]
+    fn placement_follows_the_rules() {
+        let mut world = channel();
+        let settings = PrefabSettings::default();
+        let placed = place_prefabs(&mut world, &settings);
+        let bridges: Vec<_> = placed.iter().filter(|p| p.placement == 0).collect();
+        let trees: Vec<_> = placed.iter().filter(|p| p.placement == 2).collect();
+        assert!(!bridges.is_empty());
+        assert!(!trees.is_empty());
+        for bridge in bridges {
+            // spans the channel resting on the water surface
+            assert!(bridge.origin[0] < 30 && bridge.origin[0] + 12 >= 37);
+            assert_eq!(bridge.origin[1], 10);
+        }
+        for tree in trees {
+            // dry and within reach of the channel, the trunk is sunk into the ground
+            let x = tree.origin[0];
+            assert!(!(26..37).contains(&x));
+            assert!(x + 5 + 16 > 30 && x - 16 < 37);
+            assert_eq!(tree.origin[1], 9);
+        }
+    }
+
+    #[test]
+    fn flooded_caves_are_not_near_water() {
+        // the channel runs below the ground
+        let mut world = channel();
+        let ground = world.ground.get_mut(&[0, 0]).unwrap();
+        let water = world.water.get_mut(&[0, 0]).unwrap();
+        for x in 30..37 {
+            for z in 0..64 {
+                ground.set_pillar([x, z], low_bits(10) & !low_bits(8) | low_bits(6));
+                water.set_pillar([x, z], low_bits(8) & !low_bits(6));
+            }
+        }
+        let placed = place_prefabs(&mut world, &PrefabSettings::default());
+        assert!(placed.iter().all(|p| p.placement != 2));
+    }
+
+    #[test]
+    fn plain_terrain_ignores_the_falling_water_layer() {
+        let plain = VoxelWorld::gen(-1..1, -1..1);
+        let settings = GenSettings {
+            prefabs: Some(PrefabSettings::default()),
+            ..Default::default()
+        };
+        let world = VoxelWorld::generate(-1..1, -1..1, &settings);
+        let mut stamped = 0;
+        for (coord, chunk) in world.ground.iter() {
+            let pillars = chunk.raw.iter().zip(plain.ground[coord].raw.iter());
+            for (i, (after, before)) in pillars.enumerate() {
+                let added = after & !before;
+                if added == 0 {
+                    assert_eq!(world.water[coord].raw[i], plain.water[coord].raw[i]);
+                    continue;
+                }
+                stamped += 1;
+                // resting on the terrain below the water layer instead of floating on it
+                let lowest = added.trailing_zeros() as i32;
+                assert!(lowest <= height(*before) + 1);
+                assert!(lowest < 62);
+                // the water layer still covers the prefab, besides the water of the prefab
+                assert_eq!(world.water[coord].raw[i] & 1 << 62, (1 << 62) & !after);
+            }
+        }
+        assert!(stamped > 0);
+    }
+
+    #[test]
+    fn placement_is_deterministic_without_overlaps() {
+        let settings = GenSettings {
+            rivers: Some(RiverSettings::default()),
+            prefabs: Some(PrefabSettings::default()),
+            ..Default::default()
+        };
+        let a = VoxelWorld::generate(-1..1, -1..1, &settings);
+        let b = VoxelWorld::generate(-1..1, -1..1, &settings);
+        for (coord, chunk) in a.ground.iter() {
+            assert_eq!(chunk.raw, b.ground[coord].raw);
+            assert_eq!(a.water[coord].raw, b.water[coord].raw);
+        }
+
+        let mut world = VoxelWorld::generate(
+            -1..1,
+            -1..1,
+            &GenSettings {
+                rivers: Some(RiverSettings::default()),
+                ..Default::default()
+            },
+        );
+        let placed = place_prefabs(&mut world, &PrefabSettings::default());
+        assert!(!placed.is_empty());
+        let rects: Vec<[i32; 4]> = placed
+            .iter()
+            .map(|p| {
+                let size = PrefabSettings::default().placements[p.placement]
+                    .prefab
+                    .size;
+                let [w, d] = size.map(|s| s as i32);
+                [p.origin[0], p.origin[2], p.origin[0] + w, p.origin[2] + d]
+            })
+            .collect();
+        for (i, a) in rects.iter().enumerate() {
+            for b in rects[i + 1..].iter() {
+                assert!(a[2] <= b[0] || b[2] <= a[0] || a[3] <= b[1] || b[3] <= a[1]);
+            }
+        }
+    }
+}
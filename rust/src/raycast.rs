@@ -0,0 +1,207 @@
+use crate::voxel_storage::VoxelWorld;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HitKind {
+    Ground,
+    Water,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RayHit {
+    /// hit voxel in world space
+    pub voxel: [i32; 3],
+    /// normal of the face the ray entered the voxel through, zero if the ray started inside of it
+    pub normal: [i32; 3],
+    /// distance from the origin to the entry point, in units of the direction
+    pub distance: f32,
+    pub kind: HitKind,
+}
+
+/// Walks the voxels along the ray (Amanatides & Woo DDA) and returns the first ground or,
+/// if `hit_water` is set, water voxel. Positions are in voxel units relative to the world origin.
+/// The ray ends where it leaves the world, so an infinite `max_distance` is fine.
+pub fn raycast(
+    world: &VoxelWorld,
+    origin: [f32; 3],
+    direction: [f32; 3],
+    max_distance: f32,
+    hit_water: bool,
+) -> Option<RayHit> {
+    let length =
+        (direction[0] * direction[0] + direction[1] * direction[1] + direction[2] * direction[2])
+            .sqrt();
+    if length == 0.0 || !length.is_finite() || origin.iter().any(|o| !o.is_finite()) {
+        return None;
+    }
+    let direction = direction.map(|d| d / length);
+
+    // the world in voxels, the upper bounds are excluded
+    let min = [world.xs.start as i32 * 64, 0, world.zs.start as i32 * 64];
+    let max = [world.xs.end as i32 * 64, 64, world.zs.end as i32 * 64];
+    let farthest = [0, 1, 2].map(|a| {
+        let d = (origin[a] - min[a] as f32)
+            .abs()
+            .max((origin[a] - max[a] as f32).abs());
+        d * d
+    });
+    let max_distance = max_distance.min((farthest[0] + farthest[1] + farthest[2]).sqrt());
+
+    let mut voxel = VoxelWorld::voxel_at(origin);
+    let mut step = [0i32; 3];
+    let mut t_max = [f32::INFINITY; 3];
+    let mut t_delta = [f32::INFINITY; 3];
+    for axis in 0..3 {
+        if direction[axis] > 0.0 {
+            step[axis] = 1;
+            t_max[axis] = (voxel[axis] as f32 + 1.0 - origin[axis]) / direction[axis];
+            t_delta[axis] = 1.0 / direction[axis];
+        } else if direction[axis] < 0.0 {
+            step[axis] = -1;
+            t_max[axis] = (voxel[axis] as f32 - origin[axis]) / direction[axis];
+            t_delta[axis] = -1.0 / direction[axis];
+        }
+    }
+
+    let mut normal = [0i32; 3];
+    let mut distance = 0.0;
+    loop {
+        if let Some(kind) = hit_kind(world, voxel, hit_water) {
+            return Some(RayHit {
+                voxel,
+                normal,
+                distance,
+                kind,
+            });
+        }
+        // outside of the world and moving away from it
+        if (0..3)
+            .any(|a| (voxel[a] < min[a] && step[a] <= 0) || (voxel[a] >= max[a] && step[a] >= 0))
+        {
+            return None;
+        }
+        let axis = if t_max[0] < t_max[1] {
+            if t_max[0] < t_max[2] {
+                0
+            } else {
+                2
+            }
+        } else if t_max[1] < t_max[2] {
+            1
+        } else {
+            2
+        };
+        if t_max[axis] > max_distance {
+            return None;
+        }
+        voxel[axis] += step[axis];
+        distance = t_max[axis];
+        t_max[axis] += t_delta[axis];
+        normal = [0; 3];
+        normal[axis] = -step[axis];
+    }
+}
+
+fn hit_kind(world: &VoxelWorld, voxel: [i32; 3], hit_water: bool) -> Option<HitKind> {
+    let (chunk, local) = world.locate(voxel)?;
+    if world.ground[&chunk].get(local) {
+        Some(HitKind::Ground)
+    } else if hit_water && world.water[&chunk].get(local) {
+        Some(HitKind::Water)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::voxel_storage::VoxelWorld;
+
+    use super::{raycast, HitKind, RayHit};
+
+    fn floor_world() -> VoxelWorld {
+        let mut world = VoxelWorld::empty(-1..1, 0..1);
+        for ground in world.ground.values_mut() {
+            for x in 0..64 {
+                for z in 0..64 {
+                    ground.set([x, 0, z]);
+                }
+            }
+        }
+        world.water.get_mut(&[0, 0]).unwrap().set([5, 1, 5]);
+        world.ground.get_mut(&[0, 0]).unwrap().set([0, 3, 10]);
+        world
+    }
+
+    #[test]
+    fn hit_floor_from_above() {
+        let world = floor_world();
+        let hit = raycast(&world, [-10.5, 20.0, 3.5], [0.0, -1.0, 0.0], 100.0, true);
+        assert_eq!(
+            hit,
+            Some(RayHit {
+                voxel: [-11, 0, 3],
+                normal: [0, 1, 0],
+                distance: 19.0,
+                kind: HitKind::Ground,
+            })
+        );
+        assert_eq!(
+            raycast(&world, [-10.5, 20.0, 3.5], [0.0, -1.0, 0.0], 10.0, true),
+            None
+        );
+    }
+
+    #[test]
+    fn water_is_optional() {
+        let world = floor_world();
+        let water = raycast(&world, [5.5, 10.0, 5.5], [0.0, -2.0, 0.0], 100.0, true).unwrap();
+        assert_eq!(water.voxel, [5, 1, 5]);
+        assert_eq!(water.kind, HitKind::Water);
+        let ground = raycast(&world, [5.5, 10.0, 5.5], [0.0, -2.0, 0.0], 100.0, false).unwrap();
+        assert_eq!(ground.voxel, [5, 0, 5]);
+        assert_eq!(ground.kind, HitKind::Ground);
+    }
+
+    #[test]
+    fn crosses_chunk_border() {
+        let world = floor_world();
+        let hit = raycast(&world, [-20.5, 3.5, 10.5], [1.0, 0.0, 0.0], 100.0, true).unwrap();
+        assert_eq!(hit.voxel, [0, 3, 10]);
+        assert_eq!(hit.normal, [-1, 0, 0]);
+        assert_eq!(hit.distance, 20.5);
+
+        let diagonal = raycast(&world, [-0.5, 2.0, 0.5], [1.0, -1.0, 0.0], 100.0, false).unwrap();
+        assert_eq!(diagonal.voxel, [0, 0, 0]);
+        assert_eq!(diagonal.normal, [0, 1, 0]);
+    }
+
+    #[test]
+    fn misses_outside_of_world() {
+        let world = floor_world();
+        assert_eq!(
+            raycast(&world, [0.5, 10.0, 0.5], [0.0, 1.0, 0.0], 100.0, true),
+            None
+        );
+        assert_eq!(
+            raycast(&world, [0.5, 10.0, 0.5], [0.0, 0.0, 0.0], 100.0, true),
+            None
+        );
+        assert_eq!(
+            raycast(
+                &world,
+                [0.5, 10.0, 0.5],
+                [1.0, 0.0, 0.0],
+                f32::INFINITY,
+                true
+            ),
+            None
+        );
+        assert_eq!(
+            raycast(&world, [0.5, 10.0, 0.5], [f32::NAN, 0.0, 0.0], 100.0, true),
+            None
+        );
+        // rays from outside still enter the world
+        let hit = raycast(&world, [3.5, 1e6, 3.5], [0.0, -1.0, 0.0], f32::MAX, true).unwrap();
+        assert_eq!(hit.voxel, [3, 0, 3]);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::voxel_storage::{VoxelStorage, VoxelWorld};
+
+const MAGIC: &[u8; 4] = b"VXWL";
+const VERSION: u32 = 1;
+
+/// Save file of the voxels: magic, version, the chunk ranges as four i8 and then per chunk, x
+/// major, the ground and water pillars as little endian u64.
+pub fn save_world(world: &VoxelWorld) -> Vec<u8> {
+    let chunks = world.xs.len() * world.zs.len();
+    let mut bytes = Vec::with_capacity(12 + chunks * 2 * 64 * 64 * 8);
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    for v in [world.xs.start, world.xs.end, world.zs.start, world.zs.end] {
+        bytes.extend_from_slice(&v.to_le_bytes());
+    }
+    for x in world.xs.clone() {
+        for z in world.zs.clone() {
+            for storage in [&world.ground[&[x, z]], &world.water[&[x, z]]] {
+                for pillar in storage.raw.iter() {
+                    bytes.extend_from_slice(&pillar.to_le_bytes());
+                }
+            }
+        }
+    }
+    bytes
+}
+
+/// reads a file written by `save_world`, None if its length does not match the chunk ranges or
+/// it has a different version
+pub fn load_world(bytes: &[u8]) -> Option<VoxelWorld> {
+    if bytes.get(0..4)? != MAGIC || bytes.get(4..8)? != VERSION.to_le_bytes() {
+        return None;
+    }
+    let [xs_start, xs_end, zs_start, zs_end] = <[u8; 4]>::try_from(bytes.get(8..12)?)
+        .ok()?
+        .map(|b| b as i8);
+    if xs_start > xs_end || zs_start > zs_end {
+        return None;
+    }
+    // check the length before allocating the chunks the header asks for
+    let chunks = xs_start.abs_diff(xs_end) as usize * zs_start.abs_diff(zs_end) as usize;
+    if bytes.len() != 12 + chunks * 2 * 64 * 64 * 8 {
+        return None;
+    }
+    let mut world = VoxelWorld::empty(xs_start..xs_end, zs_start..zs_end);
+    let mut pillars = bytes[12..].chunks_exact(8);
+    let mut read = || -> Option<VoxelStorage> {
+        let mut storage = VoxelStorage::empty();
+        for pillar in storage.raw.iter_mut() {
+            *pillar = u64::from_le_bytes(pillars.next()?.try_into().unwrap());
+        }
+        Some(storage)
+    };
+    for x in xs_start..xs_end {
+        for z in zs_start..zs_end {
+            world.ground.insert([x, z], read()?);
+            world.water.insert([x, z], read()?);
+        }
+    }
+    Some(world)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::voxel_storage::VoxelWorld;
+
+    use super::{load_world, save_world};
+
+    #[test]
+    fn round_trip() {
+        let world = VoxelWorld::gen(-1..1, 0..1);
+        let bytes = save_world(&world);
+        let loaded = load_world(&bytes).unwrap();
+        assert_eq!(loaded.xs, -1..1);
+        assert_eq!(loaded.zs, 0..1);
+        for (coord, ground) in world.ground.iter() {
+            assert_eq!(loaded.ground[coord].raw, ground.raw);
+            assert_eq!(loaded.water[coord].raw, world.water[coord].raw);
+        }
+        assert!(load_world(&bytes[..bytes.len() - 1]).is_none());
+        assert!(load_world(b"VXWL").is_none());
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(load_world(&trailing).is_none());
+        // a header asking for the largest world without the data for it
+        let mut header = bytes[..8].to_vec();
+        header.extend_from_slice(&[0x80, 0x7f, 0x80, 0x7f]);
+        assert!(load_world(&header).is_none());
+        let mut reversed = bytes[..8].to_vec();
+        reversed.extend_from_slice(&[1, 0, 0, 1]);
+        assert!(load_world(&reversed).is_none());
+    }
+}
@@ -0,0 +1,196 @@
+use crate::mesh_data::{triangle_normal, MeshData};
+
+/// density of a voxel world that only knows solid and empty
+pub fn binary_density(solid: bool) -> f32 {
+    if solid {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+// samples span the voxels -1..=64 on every axis
+const SAMPLES: i32 = 66;
+
+fn sample_index(p: [i32; 3]) -> usize {
+    ((p[0] + 1) + (p[1] + 1) * SAMPLES + (p[2] + 1) * SAMPLES * SAMPLES) as usize
+}
+
+/// Surface nets over the voxel centers of a chunk. `density` is positive inside of the ground,
+/// positions are relative to the chunk and reach one voxel into the neighbouring chunks, so
+/// meshes of neighbouring chunks line up. Every vertex is shared by all quads around it.
+/// The mesh has white vertex colors and no uvs or tangents.
+pub fn surface_nets(density: impl Fn([i32; 3]) -> f32) -> MeshData {
+    let mut samples = vec![0.0f32; (SAMPLES * SAMPLES * SAMPLES) as usize];
+    for z in -1..SAMPLES - 1 {
+        for y in -1..SAMPLES - 1 {
+            for x in -1..SAMPLES - 1 {
+                samples[sample_index([x, y, z])] = density([x, y, z]);
+            }
+        }
+    }
+    let sample = |p: [i32; 3]| samples[sample_index(p)];
+
+    let mut mesh = MeshData::default();
+    // vertex index per cell, the cell c spans the voxel centers c..=c + 1
+    let mut cell_vertex = vec![-1i32; (SAMPLES * SAMPLES * SAMPLES) as usize];
+    for z in -1..64 {
+        for y in -1..64 {
+            for x in -1..64 {
+                let c = [x, y, z];
+                let mut sum = [0.0f32; 3];
+                let mut crossings = 0;
+                for (a, b) in CELL_EDGES {
+                    let pa = [c[0] + a[0], c[1] + a[1], c[2] + a[2]];
+                    let pb = [c[0] + b[0], c[1] + b[1], c[2] + b[2]];
+                    let (da, db) = (sample(pa), sample(pb));
+                    if (da > 0.0) == (db > 0.0) {
+                        continue;
+                    }
+                    let t = da / (da - db);
+                    for axis in 0..3 {
+                        sum[axis] += pa[axis] as f32 + t * (pb[axis] - pa[axis]) as f32 + 0.5;
+                    }
+                    crossings += 1;
+                }
+                if crossings > 0 {
+                    cell_vertex[sample_index(c)] = mesh.positions.len() as i32;
+                    mesh.positions.push(sum.map(|s| s / crossings as f32));
+                }
+            }
+        }
+    }
+
+    // every sign change between a voxel of the chunk and its +x, +y or +z neighbour is a quad
+    // connecting the four cells around that edge
+    for z in 0..64 {
+        for y in -1..64 {
+            for x in 0..64 {
+                let p = [x, y, z];
+                let inside = sample(p) > 0.0;
+                for axis in 0..3 {
+                    // below the chunk only the edges up into it are part of the surface
+                    if y < 0 && axis != 1 {
+                        continue;
+                    }
+                    let mut q = p;
+                    q[axis] += 1;
+                    if inside == (sample(q) > 0.0) {
+                        continue;
+                    }
+                    let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+                    let mut corners = [0i32; 4];
+                    for (i, [du, dv]) in [[-1, -1], [0, -1], [0, 0], [-1, 0]].iter().enumerate() {
+                        let mut c = p;
+                        c[u] += du;
+                        c[v] += dv;
+                        corners[i] = cell_vertex[sample_index(c)];
+                    }
+                    let mut outward = [0.0; 3];
+                    outward[axis] = if inside { 1.0 } else { -1.0 };
+                    push_quad(&mut mesh, corners, outward);
+                }
+            }
+        }
+    }
+
+    let mut normals = vec![[0.0f32; 3]; mesh.positions.len()];
+    for (triangle, corners) in mesh.indices.chunks(3).zip(mesh.triangles()) {
+        let n = triangle_normal(corners);
+        for &i in triangle {
+            let normal = &mut normals[i as usize];
+            *normal = [0, 1, 2].map(|axis| normal[axis] + n[axis]);
+        }
+    }
+    mesh.normals = normals.into_iter().map(normalize).collect();
+    mesh.colors = vec![[1.0; 4]; mesh.positions.len()];
+    mesh
+}
+
+const CELL_EDGES: [([i32; 3], [i32; 3]); 12] = [
+    ([0, 0, 0], [1, 0, 0]),
+    ([0, 1, 0], [1, 1, 0]),
+    ([0, 0, 1], [1, 0, 1]),
+    ([0, 1, 1], [1, 1, 1]),
+    ([0, 0, 0], [0, 1, 0]),
+    ([1, 0, 0], [1, 1, 0]),
+    ([0, 0, 1], [0, 1, 1]),
+    ([1, 0, 1], [1, 1, 1]),
+    ([0, 0, 0], [0, 0, 1]),
+    ([1, 0, 0], [1, 0, 1]),
+    ([0, 1, 0], [0, 1, 1]),
+    ([1, 1, 0], [1, 1, 1]),
+];
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if length == 0.0 {
+        v
+    } else {
+        v.map(|c| c / length)
+    }
+}
+
+fn push_quad(mesh: &mut MeshData, corners: [i32; 4], outward: [f32; 3]) {
+    let mut triangles = [
+        corners[0], corners[1], corners[2], corners[0], corners[2], corners[3],
+    ];
+    let n = triangle_normal([0, 1, 2].map(|i| mesh.positions[triangles[i] as usize]));
+    if n[0] * outward[0] + n[1] * outward[1] + n[2] * outward[2] < 0.0 {
+        triangles.swap(1, 2);
+        triangles.swap(4, 5);
+    }
+    mesh.indices.extend_from_slice(&triangles);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mesh_data::triangle_normal;
+
+    use super::{binary_density, surface_nets};
+
+    fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+        a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+    }
+
+    #[test]
+    fn flat_ground() {
+        // solid below y = 10, neighbouring chunks continue the ground
+        let mesh = surface_nets(|p| binary_density(p[1] < 10));
+        assert_eq!(mesh.indices.len(), 64 * 64 * 6);
+        assert!(mesh.positions.iter().all(|p| p[1] == 10.0));
+        assert!(mesh.normals.iter().all(|n| *n == [0.0, 1.0, 0.0]));
+        for triangle in mesh.triangles() {
+            assert!(triangle_normal(triangle)[1] > 0.0);
+        }
+    }
+
+    #[test]
+    fn closed_blob() {
+        let center = [20.5, 20.5, 20.5];
+        let mesh = surface_nets(|p| {
+            let d = [0, 1, 2].map(|i| p[i] as f32 + 0.5 - center[i]);
+            4.0 - dot(d, d).sqrt()
+        });
+        assert!(!mesh.indices.is_empty());
+        // all faces point away from the center
+        for triangle in mesh.triangles() {
+            let a = triangle[0];
+            let n = triangle_normal(triangle);
+            assert!(dot(n, [a[0] - center[0], a[1] - center[1], a[2] - center[2]]) > 0.0);
+        }
+        // a closed surface uses every edge exactly twice, in opposite directions
+        let mut edges = std::collections::HashMap::new();
+        for triangle in mesh.indices.chunks(3) {
+            for i in 0..3 {
+                *edges
+                    .entry((triangle[i], triangle[(i + 1) % 3]))
+                    .or_insert(0) += 1;
+            }
+        }
+        for ((a, b), count) in edges.iter() {
+            assert_eq!(*count, 1);
+            assert_eq!(edges.get(&(*b, *a)), Some(&1));
+        }
+    }
+}
@@ -0,0 +1,46 @@
+use godot::engine::ILabel;
+use godot::engine::Label;
+use godot::prelude::*;
+
+use crate::find_world;
+
+/// Debug label showing the water statistics of the world, refreshed every `interval` seconds.
+#[derive(GodotClass)]
+#[class(base=Label)]
+struct WaterStatsOverlay {
+    base: Base<Label>,
+    #[export]
+    interval: f64,
+    /// number of basins listed, largest first
+    #[export]
+    max_basins: i32,
+    elapsed: f64,
+}
+
+#[godot_api]
+impl ILabel for WaterStatsOverlay {
+    fn init(base: Base<Label>) -> Self {
+        WaterStatsOverlay {
+            base,
+            interval: 0.5,
+            max_basins: 5,
+            elapsed: f64::MAX,
+        }
+    }
+
+    fn process(&mut self, delta: f64) {
+        self.elapsed += delta;
+        if self.elapsed < self.interval {
+            return;
+        }
+        self.elapsed = 0.0;
+        let Some(world) = find_world(self.base().get_tree()) else {
+            return;
+        };
+        let text = world
+            .bind()
+            .water_statistics()
+            .summary(self.max_basins.max(0) as usize);
+        self.base_mut().set_text(text.into());
+    }
+}
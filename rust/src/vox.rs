@@ -0,0 +1,412 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::voxel_storage::VoxelWorld;
+
+/// How palette indices of a `.vox` file map onto the materials of the world: voxels with the
+/// `water` index become water, every other index becomes ground.
+pub struct VoxPalette {
+    pub ground: u8,
+    pub water: u8,
+}
+
+impl Default for VoxPalette {
+    fn default() -> Self {
+        VoxPalette {
+            ground: 1,
+            water: 2,
+        }
+    }
+}
+
+const VERSION: i32 = 150;
+/// chunks per side of the world read from a file
+const MAX_CHUNKS: i32 = 32;
+
+/// Reads a MagicaVoxel file. Models are placed by the translations of the scene graph, rotations
+/// are ignored. MagicaVoxel's z axis points up, it becomes the y axis of the world and its y axis
+/// the negative z axis. The chunk range covers all voxels, voxels outside of the height range
+/// 0..64 are dropped. Returns None for files that are not in the `.vox` format and for scenes
+/// that span more than `MAX_CHUNKS` chunks along x or z.
+pub fn read_vox(bytes: &[u8], palette: &VoxPalette) -> Option<VoxelWorld> {
+    let mut reader = Reader { bytes, at: 0 };
+    if reader.take(4)? != b"VOX " {
+        return None;
+    }
+    reader.i32()?;
+    let (id, content, children) = reader.chunk()?;
+    if id != *b"MAIN" || !content.is_empty() {
+        return None;
+    }
+
+    let mut models: Vec<Vec<[u8; 4]>> = Vec::new();
+    let mut sizes: Vec<[i32; 3]> = Vec::new();
+    let mut transforms: HashMap<i32, ([i32; 3], i32)> = HashMap::new();
+    let mut groups: HashMap<i32, Vec<i32>> = HashMap::new();
+    let mut shapes: HashMap<i32, Vec<i32>> = HashMap::new();
+    let mut reader = Reader {
+        bytes: children,
+        at: 0,
+    };
+    while reader.at < reader.bytes.len() {
+        let (id, content, _) = reader.chunk()?;
+        let mut content = Reader {
+            bytes: content,
+            at: 0,
+        };
+        match &id {
+            b"SIZE" => sizes.push([content.i32()?, content.i32()?, content.i32()?]),
+            b"XYZI" => {
+                let count = content.i32()?;
+                let voxels = (0..count)
+                    .map(|_| Some(content.take(4)?.try_into().unwrap()))
+                    .collect::<Option<Vec<[u8; 4]>>>()?;
+                models.push(voxels);
+            }
+            b"nTRN" => {
+                let node = content.i32()?;
+                content.dict()?;
+                let child = content.i32()?;
+                content.i32()?;
+                content.i32()?;
+                let frames = content.i32()?;
+                let mut translation = [0; 3];
+                for _ in 0..frames {
+                    if let Some(t) = content.dict()?.get("_t") {
+                        let values: Vec<i32> =
+                            t.split(' ').filter_map(|v| v.parse().ok()).collect();
+                        translation = values.try_into().ok()?;
+                    }
+                }
+                transforms.insert(node, (translation, child));
+            }
+            b"nGRP" => {
+                let node = content.i32()?;
+                content.dict()?;
+                let count = content.i32()?;
+                let children = (0..count)
+                    .map(|_| content.i32())
+                    .collect::<Option<Vec<_>>>()?;
+                groups.insert(node, children);
+            }
+            b"nSHP" => {
+                let node = content.i32()?;
+                content.dict()?;
+                let count = content.i32()?;
+                let mut ids = Vec::new();
+                for _ in 0..count {
+                    ids.push(content.i32()?);
+                    content.dict()?;
+                }
+                shapes.insert(node, ids);
+            }
+            _ => (),
+        }
+    }
+    if models.len() != sizes.len() {
+        return None;
+    }
+
+    // files without a scene graph keep every model at the origin, without centering
+    let mut placements: Vec<(usize, [i32; 3])> = Vec::new();
+    if transforms.contains_key(&0) {
+        let mut open = vec![(0, [0; 3])];
+        let mut visited = HashSet::new();
+        while let Some((node, offset)) = open.pop() {
+            // the scene graph is a tree, a node reached twice means the file is corrupt
+            if !visited.insert(node) {
+                return None;
+            }
+            if let Some((t, child)) = transforms.get(&node) {
+                open.push((*child, [0, 1, 2].map(|i| offset[i] + t[i])));
+            } else if let Some(children) = groups.get(&node) {
+                open.extend(children.iter().map(|c| (*c, offset)));
+            } else if let Some(ids) = shapes.get(&node) {
+                for id in ids {
+                    let size = sizes.get(*id as usize)?;
+                    // the translation is the center of the model
+                    placements.push((*id as usize, [0, 1, 2].map(|i| offset[i] - size[i] / 2)));
+                }
+            }
+        }
+    } else {
+        placements.extend((0..models.len()).map(|id| (id, [0; 3])));
+    }
+
+    let mut voxels: Vec<([i32; 3], u8)> = Vec::new();
+    for (id, origin) in placements {
+        for &[x, y, z, index] in models[id].iter() {
+            let p = [
+                origin[0] + x as i32,
+                origin[1] + y as i32,
+                origin[2] + z as i32,
+            ];
+            voxels.push(([p[0], p[2], -p[1] - 1], index));
+        }
+    }
+    voxels.retain(|(p, _)| (0..64).contains(&p[1]));
+    let chunk = |c: i32| i8::try_from(c.div_euclid(64)).ok();
+    let mut range = [i8::MAX, i8::MIN, i8::MAX, i8::MIN];
+    for (p, _) in voxels.iter() {
+        let (x, z) = (chunk(p[0])?, chunk(p[2])?);
+        range = [
+            range[0].min(x),
+            range[1].max(x),
+            range[2].min(z),
+            range[3].max(z),
+        ];
+    }
+    if voxels.is_empty() {
+        range = [0, 0, 0, 0];
+    }
+    // the translations come from the file, don't allocate whatever they span
+    if range[1] as i32 - range[0] as i32 >= MAX_CHUNKS
+        || range[3] as i32 - range[2] as i32 >= MAX_CHUNKS
+    {
+        return None;
+    }
+    let mut world = VoxelWorld::empty(range[0]..range[1] + 1, range[2]..range[3] + 1);
+    for (p, index) in voxels {
+        let (chunk, local) = world.locate(p)?;
+        let target = if index == palette.water {
+            &mut world.water
+        } else {
+            &mut world.ground
+        };
+        target.get_mut(&chunk)?.set(local);
+    }
+    Some(world)
+}
+
+/// Writes every chunk as a 64 x 64 x 64 model, placed by a translation in the scene graph.
+/// Ground and water use the palette indices of `palette`, the palette colors them green and
+/// blue. `read_vox` reads the file back into the same voxels.
+pub fn write_vox(world: &VoxelWorld, palette: &VoxPalette) -> Vec<u8> {
+    let mut coords: Vec<[i8; 2]> = world.ground.keys().cloned().collect();
+    coords.sort();
+
+    let mut children = Vec::new();
+    for coord in coords.iter() {
+        let mut size = Vec::new();
+        for v in [64, 64, 64] {
+            size.extend_from_slice(&i32::to_le_bytes(v));
+        }
+        push_chunk(&mut children, b"SIZE", &size, &[]);
+
+        let mut voxels = Vec::new();
+        for (storage, index) in [
+            (&world.ground[coord], palette.ground),
+            (&world.water[coord], palette.water),
+        ] {
+            for z in 0..64u8 {
+                for x in 0..64u8 {
+                    let pillar = storage.get_pillar([x, z]);
+                    for y in (0..64u8).filter(|y| (pillar >> y) & 1 == 1) {
+                        // local vox y runs against the world z axis
+                        voxels.extend_from_slice(&[x, 63 - z, y, index]);
+                    }
+                }
+            }
+        }
+        let mut xyzi = i32::to_le_bytes(voxels.len() as i32 / 4).to_vec();
+        xyzi.extend_from_slice(&voxels);
+        push_chunk(&mut children, b"XYZI", &xyzi, &[]);
+    }
+
+    // root transform -> group -> (transform -> shape) per model
+    let models = coords.len() as i32;
+    push_chunk(&mut children, b"nTRN", &transform(0, 1, -1, None), &[]);
+    let mut group = i32::to_le_bytes(1).to_vec();
+    group.extend_from_slice(&i32::to_le_bytes(0));
+    group.extend_from_slice(&i32::to_le_bytes(models));
+    for i in 0..models {
+        group.extend_from_slice(&i32::to_le_bytes(2 + 2 * i));
+    }
+    push_chunk(&mut children, b"nGRP", &group, &[]);
+    for (i, coord) in coords.iter().enumerate() {
+        let i = i as i32;
+        // center of the chunk in vox coordinates
+        let center = [
+            coord[0] as i32 * 64 + 32,
+            -(coord[1] as i32) * 64 - 64 + 32,
+            32,
+        ];
+        push_chunk(
+            &mut children,
+            b"nTRN",
+            &transform(2 + 2 * i, 3 + 2 * i, 0, Some(center)),
+            &[],
+        );
+        let mut shape = Vec::new();
+        for v in [3 + 2 * i, 0, 1, i, 0] {
+            shape.extend_from_slice(&i32::to_le_bytes(v));
+        }
+        push_chunk(&mut children, b"nSHP", &shape, &[]);
+    }
+
+    let mut rgba = Vec::new();
+    for index in 1..=256u32 {
+        let color = match index {
+            i if i == palette.water as u32 => [40, 90, 220, 255],
+            i if i == palette.ground as u32 => [40, 140, 40, 255],
+            _ => [128, 128, 128, 255],
+        };
+        rgba.extend_from_slice(&color);
+    }
+    push_chunk(&mut children, b"RGBA", &rgba, &[]);
+
+    let mut bytes = b"VOX ".to_vec();
+    bytes.extend_from_slice(&i32::to_le_bytes(VERSION));
+    push_chunk(&mut bytes, b"MAIN", &[], &children);
+    bytes
+}
+
+fn push_chunk(dst: &mut Vec<u8>, id: &[u8; 4], content: &[u8], children: &[u8]) {
+    dst.extend_from_slice(id);
+    dst.extend_from_slice(&i32::to_le_bytes(content.len() as i32));
+    dst.extend_from_slice(&i32::to_le_bytes(children.len() as i32));
+    dst.extend_from_slice(content);
+    dst.extend_from_slice(children);
+}
+
+/// content of an nTRN chunk with an empty attribute dictionary and a single frame
+fn transform(node: i32, child: i32, layer: i32, translation: Option<[i32; 3]>) -> Vec<u8> {
+    let mut content = Vec::new();
+    for v in [node, 0, child, -1, layer, 1] {
+        content.extend_from_slice(&i32::to_le_bytes(v));
+    }
+    match translation {
+        Some([x, y, z]) => {
+            content.extend_from_slice(&i32::to_le_bytes(1));
+            for s in ["_t".to_string(), format!("{x} {y} {z}")] {
+                content.extend_from_slice(&i32::to_le_bytes(s.len() as i32));
+                content.extend_from_slice(s.as_bytes());
+            }
+        }
+        None => content.extend_from_slice(&i32::to_le_bytes(0)),
+    }
+    content
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.at..self.at.checked_add(n)?)?;
+        self.at += n;
+        Some(bytes)
+    }
+
+    fn i32(&mut self) -> Option<i32> {
+        Some(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let length = usize::try_from(self.i32()?).ok()?;
+        Some(String::from_utf8_lossy(self.take(length)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Option<HashMap<String, String>> {
+        let count = self.i32()?;
+        (0..count)
+            .map(|_| Some((self.string()?, self.string()?)))
+            .collect()
+    }
+
+    /// id, content and children of the next chunk
+    fn chunk(&mut self) -> Option<([u8; 4], &'a [u8], &'a [u8])> {
+        let id = self.take(4)?.try_into().unwrap();
+        let content = usize::try_from(self.i32()?).ok()?;
+        let children = usize::try_from(self.i32()?).ok()?;
+        Some((id, self.take(content)?, self.take(children)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::voxel_storage::VoxelWorld;
+
+    use super::{push_chunk, read_vox, transform, write_vox, VoxPalette};
+
+    #[test]
+    fn round_trip() {
+        let mut world = VoxelWorld::empty(-1..1, 0..2);
+        for (p, water) in [
+            ([-64, 0, 0], false),
+            ([-1, 63, 127], false),
+            ([5, 10, 70], true),
+            ([0, 0, 0], false),
+        ] {
+            let (chunk, local) = world.locate(p).unwrap();
+            let target = if water {
+                &mut world.water
+            } else {
+                &mut world.ground
+            };
+            target.get_mut(&chunk).unwrap().set(local);
+        }
+        let palette = VoxPalette::default();
+        let read = read_vox(&write_vox(&world, &palette), &palette).unwrap();
+        assert_eq!(read.xs, -1..1);
+        assert_eq!(read.zs, 0..2);
+        for (coord, ground) in world.ground.iter() {
+            assert_eq!(read.ground[coord].raw, ground.raw);
+            assert_eq!(read.water[coord].raw, world.water[coord].raw);
+        }
+    }
+
+    #[test]
+    fn too_many_chunks() {
+        let palette = VoxPalette::default();
+        let mut world = VoxelWorld::empty(0..33, 0..1);
+        for x in [0, 32 * 64] {
+            let (chunk, local) = world.locate([x, 0, 0]).unwrap();
+            world.ground.get_mut(&chunk).unwrap().set(local);
+        }
+        assert!(read_vox(&write_vox(&world, &palette), &palette).is_none());
+        let (chunk, local) = world.locate([32 * 64, 0, 0]).unwrap();
+        world.ground.get_mut(&chunk).unwrap().unset(local);
+        let read = read_vox(&write_vox(&world, &palette), &palette).unwrap();
+        assert_eq!(read.xs, 0..1);
+    }
+
+    #[test]
+    fn model_without_scene_graph() {
+        let mut children = Vec::new();
+        let mut size = Vec::new();
+        for v in [2, 2, 2] {
+            size.extend_from_slice(&i32::to_le_bytes(v));
+        }
+        push_chunk(&mut children, b"SIZE", &size, &[]);
+        let mut xyzi = i32::to_le_bytes(2).to_vec();
+        xyzi.extend_from_slice(&[0, 0, 0, 7, 1, 0, 1, 2]);
+        push_chunk(&mut children, b"XYZI", &xyzi, &[]);
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend_from_slice(&i32::to_le_bytes(150));
+        push_chunk(&mut bytes, b"MAIN", &[], &children);
+
+        let world = read_vox(&bytes, &VoxPalette::default()).unwrap();
+        assert!(world.is_ground([0, 0, -1]));
+        assert!(world.is_water([1, 1, -1]));
+        assert_eq!(world.zs, -1..0);
+        assert!(read_vox(b"not a vox file", &VoxPalette::default()).is_none());
+    }
+
+    #[test]
+    fn cyclic_scene_graph() {
+        let mut children = Vec::new();
+        // a transform pointing to a group that contains the transform again
+        push_chunk(&mut children, b"nTRN", &transform(0, 1, -1, None), &[]);
+        let mut group = Vec::new();
+        for v in [1, 0, 1, 0] {
+            group.extend_from_slice(&i32::to_le_bytes(v));
+        }
+        push_chunk(&mut children, b"nGRP", &group, &[]);
+        let mut bytes = b"VOX ".to_vec();
+        bytes.extend_from_slice(&i32::to_le_bytes(150));
+        push_chunk(&mut bytes, b"MAIN", &[], &children);
+        assert!(read_vox(&bytes, &VoxPalette::default()).is_none());
+    }
+}
@@ -1,7 +1,7 @@
 use godot::{
     builtin::{
-        PackedInt32Array, PackedVector2Array, PackedVector3Array, Variant, VariantArray, Vector2,
-        Vector3,
+        Color, PackedColorArray, PackedFloat32Array, PackedInt32Array, PackedVector2Array,
+        PackedVector3Array, Variant, VariantArray, Vector2, Vector3,
     },
     engine::{
         mesh::{ArrayType, PrimitiveType},
@@ -11,221 +11,69 @@ use godot::{
     obj::{EngineEnum, Gd, NewGd},
 };
 
-use crate::voxel_storage::Faces;
+use crate::mesh_data::MeshData;
 
-pub fn blocky(faces: &Faces) -> Gd<ArrayMesh> {
+pub fn to_array_mesh(mesh: &MeshData) -> Gd<ArrayMesh> {
     let mut m = ArrayMesh::new_gd();
-
-    let mut positions = PackedVector3Array::new();
-    let mut indices = PackedInt32Array::new();
-    let mut normals = PackedVector3Array::new();
-    let mut uvs = PackedVector2Array::new();
-    let mut i = 0;
-    for &[x, y, z] in faces.top.iter() {
-        let y = y as f32 + 1.0;
-        let down_left_x = x as f32;
-        let down_left_z = z as f32;
-        let down_right_x = x as f32 + 1.0;
-        let down_right_z = z as f32;
-        let up_left_x = x as f32;
-        let up_left_z = z as f32 + 1.0;
-        let up_right_x = x as f32 + 1.0;
-        let up_right_z = z as f32 + 1.0;
-        Vector3::new(0.0, 0.0, 0.0).to_variant();
-
-        positions.push(Vector3::new(down_left_x, y, down_left_z));
-        positions.push(Vector3::new(down_right_x, y, down_right_z));
-        positions.push(Vector3::new(up_left_x, y, up_left_z));
-        positions.push(Vector3::new(up_right_x, y, up_right_z));
-        indices.push(i * 4);
-        indices.push(i * 4 + 1);
-        indices.push(i * 4 + 2);
-        indices.push(i * 4 + 2);
-        indices.push(i * 4 + 1);
-        indices.push(i * 4 + 3);
-
-        for _ in 0..4 {
-            normals.push(Vector3::UP);
-        }
-        uvs.push(Vector2::new(0.0, 0.0));
-        uvs.push(Vector2::new(1.0, 0.0));
-        uvs.push(Vector2::new(0.0, 1.0));
-        uvs.push(Vector2::new(1.0, 1.0));
-
-        i += 1;
-    }
-    for &[x, y, z] in faces.bottom.iter() {
-        let y = y as f32;
-        let down_left_x = x as f32;
-        let down_left_z = z as f32;
-        let down_right_x = x as f32 + 1.0;
-        let down_right_z = z as f32;
-        let up_left_x = x as f32;
-        let up_left_z = z as f32 + 1.0;
-        let up_right_x = x as f32 + 1.0;
-        let up_right_z = z as f32 + 1.0;
-        Vector3::new(0.0, 0.0, 0.0).to_variant();
-
-        positions.push(Vector3::new(down_left_x, y, down_left_z));
-        positions.push(Vector3::new(down_right_x, y, down_right_z));
-        positions.push(Vector3::new(up_left_x, y, up_left_z));
-        positions.push(Vector3::new(up_right_x, y, up_right_z));
-        indices.push(i * 4);
-        indices.push(i * 4 + 2);
-        indices.push(i * 4 + 1);
-        indices.push(i * 4 + 2);
-        indices.push(i * 4 + 3);
-        indices.push(i * 4 + 1);
-
-        for _ in 0..4 {
-            normals.push(Vector3::DOWN);
-        }
-        uvs.push(Vector2::new(0.0, 0.0));
-        uvs.push(Vector2::new(1.0, 0.0));
-        uvs.push(Vector2::new(0.0, 1.0));
-        uvs.push(Vector2::new(1.0, 1.0));
-
-        i += 1;
-    }
-    for &[x, y, z] in faces.left.iter() {
-        let x = x as f32;
-        let down_left_y = y as f32;
-        let down_left_z = z as f32;
-        let down_right_y = y as f32 + 1.0;
-        let down_right_z = z as f32;
-        let up_left_y = y as f32;
-        let up_left_z = z as f32 + 1.0;
-        let up_right_y = y as f32 + 1.0;
-        let up_right_z = z as f32 + 1.0;
-        Vector3::new(0.0, 0.0, 0.0).to_variant();
-
-        positions.push(Vector3::new(x, down_left_y, down_left_z));
-        positions.push(Vector3::new(x, down_right_y, down_right_z));
-        positions.push(Vector3::new(x, up_left_y, up_left_z));
-        positions.push(Vector3::new(x, up_right_y, up_right_z));
-        indices.push(i * 4);
-        indices.push(i * 4 + 1);
-        indices.push(i * 4 + 2);
-        indices.push(i * 4 + 2);
-        indices.push(i * 4 + 1);
-        indices.push(i * 4 + 3);
-
-        for _ in 0..4 {
-            normals.push(Vector3::LEFT);
-        }
-
-        uvs.push(Vector2::new(0.0, 0.0));
-        uvs.push(Vector2::new(1.0, 0.0));
-        uvs.push(Vector2::new(0.0, 1.0));
-        uvs.push(Vector2::new(1.0, 1.0));
-        i += 1;
-    }
-    for &[x, y, z] in faces.right.iter() {
-        let x = x as f32 + 1.0;
-        let down_left_y = y as f32;
-        let down_left_z = z as f32;
-        let down_right_y = y as f32 + 1.0;
-        let down_right_z = z as f32;
-        let up_left_y = y as f32;
-        let up_left_z = z as f32 + 1.0;
-        let up_right_y = y as f32 + 1.0;
-        let up_right_z = z as f32 + 1.0;
-        Vector3::new(0.0, 0.0, 0.0).to_variant();
-
-        positions.push(Vector3::new(x, down_left_y, down_left_z));
-        positions.push(Vector3::new(x, down_right_y, down_right_z));
-        positions.push(Vector3::new(x, up_left_y, up_left_z));
-        positions.push(Vector3::new(x, up_right_y, up_right_z));
-        indices.push(i * 4);
-        indices.push(i * 4 + 2);
-        indices.push(i * 4 + 1);
-        indices.push(i * 4 + 2);
-        indices.push(i * 4 + 3);
-        indices.push(i * 4 + 1);
-
-        for _ in 0..4 {
-            normals.push(Vector3::RIGHT);
-        }
-        uvs.push(Vector2::new(0.0, 0.0));
-        uvs.push(Vector2::new(1.0, 0.0));
-        uvs.push(Vector2::new(0.0, 1.0));
-        uvs.push(Vector2::new(1.0, 1.0));
-
-        i += 1;
-    }
-    for &[x, y, z] in faces.back.iter() {
-        let z = z as f32 + 1.0;
-        let down_left_y = y as f32;
-        let down_left_x = x as f32;
-        let down_right_y = y as f32 + 1.0;
-        let down_right_x = x as f32;
-        let up_left_y = y as f32;
-        let up_left_x = x as f32 + 1.0;
-        let up_right_y = y as f32 + 1.0;
-        let up_right_x = x as f32 + 1.0;
-        Vector3::new(0.0, 0.0, 0.0).to_variant();
-
-        positions.push(Vector3::new(down_left_x, down_left_y, z));
-        positions.push(Vector3::new(down_right_x, down_right_y, z));
-        positions.push(Vector3::new(up_left_x, up_left_y, z));
-        positions.push(Vector3::new(up_right_x, up_right_y, z));
-        indices.push(i * 4);
-        indices.push(i * 4 + 1);
-        indices.push(i * 4 + 2);
-        indices.push(i * 4 + 2);
-        indices.push(i * 4 + 1);
-        indices.push(i * 4 + 3);
-
-        for _ in 0..4 {
-            normals.push(Vector3::BACK);
-        }
-        uvs.push(Vector2::new(0.0, 0.0));
-        uvs.push(Vector2::new(1.0, 0.0));
-        uvs.push(Vector2::new(0.0, 1.0));
-        uvs.push(Vector2::new(1.0, 1.0));
-
-        i += 1;
-    }
-    for &[x, y, z] in faces.front.iter() {
-        let z = z as f32;
-        let down_left_y = y as f32;
-        let down_left_x = x as f32;
-        let down_right_y = y as f32 + 1.0;
-        let down_right_x = x as f32;
-        let up_left_y = y as f32;
-        let up_left_x = x as f32 + 1.0;
-        let up_right_y = y as f32 + 1.0;
-        let up_right_x = x as f32 + 1.0;
-        Vector3::new(0.0, 0.0, 0.0).to_variant();
-
-        positions.push(Vector3::new(down_left_x, down_left_y, z));
-        positions.push(Vector3::new(down_right_x, down_right_y, z));
-        positions.push(Vector3::new(up_left_x, up_left_y, z));
-        positions.push(Vector3::new(up_right_x, up_right_y, z));
-        indices.push(i * 4);
-        indices.push(i * 4 + 2);
-        indices.push(i * 4 + 1);
-        indices.push(i * 4 + 2);
-        indices.push(i * 4 + 3);
-        indices.push(i * 4 + 1);
-
-        for _ in 0..4 {
-            normals.push(Vector3::FORWARD);
-        }
-        uvs.push(Vector2::new(0.0, 0.0));
-        uvs.push(Vector2::new(1.0, 0.0));
-        uvs.push(Vector2::new(0.0, 1.0));
-        uvs.push(Vector2::new(1.0, 1.0));
-
-        i += 1;
-    }
+    let positions = PackedVector3Array::from(
+        mesh.positions
+            .iter()
+            .map(|&[x, y, z]| Vector3::new(x, y, z))
+            .collect::<Vec<_>>()
+            .as_slice(),
+    );
+    let normals = PackedVector3Array::from(
+        mesh.normals
+            .iter()
+            .map(|&[x, y, z]| Vector3::new(x, y, z))
+            .collect::<Vec<_>>()
+            .as_slice(),
+    );
+    let uvs = PackedVector2Array::from(
+        mesh.uvs
+            .iter()
+            .map(|&[u, v]| Vector2::new(u, v))
+            .collect::<Vec<_>>()
+            .as_slice(),
+    );
+    let colors = PackedColorArray::from(
+        mesh.colors
+            .iter()
+            .map(|&[r, g, b, a]| Color::from_rgba(r, g, b, a))
+            .collect::<Vec<_>>()
+            .as_slice(),
+    );
+    let tangents = PackedFloat32Array::from(mesh.tangents.concat().as_slice());
 
     let mut variant_array = VariantArray::new();
     variant_array.resize(ArrayType::MAX.ord() as usize, &Variant::nil());
     variant_array.set(ArrayType::VERTEX.ord() as usize, positions.to_variant());
-    variant_array.set(ArrayType::INDEX.ord() as usize, indices.to_variant());
+    variant_array.set(
+        ArrayType::INDEX.ord() as usize,
+        PackedInt32Array::from(mesh.indices.as_slice()).to_variant(),
+    );
     variant_array.set(ArrayType::NORMAL.ord() as usize, normals.to_variant());
-    variant_array.set(ArrayType::TEX_UV.ord() as usize, uvs.to_variant());
+    // attributes the mesher did not produce are left out of the surface
+    if !mesh.tangents.is_empty() {
+        variant_array.set(ArrayType::TANGENT.ord() as usize, tangents.to_variant());
+    }
+    if !mesh.uvs.is_empty() {
+        variant_array.set(ArrayType::TEX_UV.ord() as usize, uvs.to_variant());
+    }
+    if !mesh.colors.is_empty() {
+        variant_array.set(ArrayType::COLOR.ord() as usize, colors.to_variant());
+    }
     m.add_surface_from_arrays(PrimitiveType::TRIANGLES, variant_array);
     m
 }
+
+/// triangle list of the mesh, for a `ConcavePolygonShape3D`
+pub fn collision_faces(mesh: &MeshData) -> PackedVector3Array {
+    let mut triangles = PackedVector3Array::new();
+    for triangle in mesh.triangles() {
+        for [x, y, z] in triangle {
+            triangles.push(Vector3::new(x, y, z));
+        }
+    }
+    triangles
+}
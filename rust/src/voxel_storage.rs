@@ -1,6 +1,11 @@
 use std::{collections::HashMap, ops::Range};
 
-use noise::{Fbm, NoiseFn, OpenSimplex};
+use noise::{Fbm, NoiseFn, OpenSimplex, RidgedMulti};
+
+use crate::caves::{carve_caves, CaveSettings};
+use crate::erosion::{erode, ErosionSettings};
+use crate::hydrology::{rivers_and_lakes, RiverSettings};
+use crate::prefab::{place_prefabs, PrefabSettings};
 
 pub type ChunkStorage = HashMap<[i8; 2], VoxelStorage>;
 
@@ -11,22 +16,174 @@ pub struct VoxelWorld {
     pub water: ChunkStorage,
 }
 
+/// optional passes of `VoxelWorld::generate`, the default generates the plain noise terrain
+#[derive(Default)]
+pub struct GenSettings {
+    /// droplet erosion of the terrain surface, runs before all other passes
+    pub erosion: Option<ErosionSettings>,
+    /// carved into the ground before the water is filled in
+    pub caves: Option<CaveSettings>,
+    /// Replaces the single noise height with the blended heights of the biomes, and the water
+    /// layer with water up to the blended water level of the biomes. Caves below the water level
+    /// are flooded.
+    pub biomes: Option<BiomeSettings>,
+    /// Carves rivers and fills lakes after the caves. Replaces the water layer of the plain
+    /// terrain, the water of the biomes is kept.
+    pub rivers: Option<RiverSettings>,
+    /// Stamps trees, ruins and bridges onto the finished terrain and water. The water layer of
+    /// the plain terrain still has to fall, it is added after the prefabs and does not count as
+    /// water for their placement.
+    pub prefabs: Option<PrefabSettings>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Biome {
+    Desert,
+    Plains,
+    Mountains,
+}
+
+impl Biome {
+    /// order of the weights returned by `BiomeMap::weights`
+    pub const ALL: [Biome; 3] = [Biome::Desert, Biome::Plains, Biome::Mountains];
+
+    /// temperature and moisture the biome is centred on
+    fn climate(self) -> [f64; 2] {
+        match self {
+            Biome::Desert => [0.3, -0.3],
+            Biome::Plains => [0.0, 0.2],
+            Biome::Mountains => [-0.3, -0.1],
+        }
+    }
+
+    /// basins are filled with water up to this height, 0 keeps them dry
+    fn water_level(self) -> f64 {
+        match self {
+            Biome::Desert => 0.0,
+            Biome::Plains => 28.0,
+            Biome::Mountains => 22.0,
+        }
+    }
+}
+
+pub struct BiomeSettings {
+    pub seed: u32,
+    /// frequency of the temperature and moisture noise, lower values give larger biomes
+    pub frequency: f64,
+    /// distance in climate space over which neighbouring biomes blend into each other
+    pub blend: f64,
+}
+
+impl Default for BiomeSettings {
+    fn default() -> Self {
+        BiomeSettings {
+            seed: 0,
+            frequency: 0.006,
+            blend: 0.15,
+        }
+    }
+}
+
+/// temperature and moisture maps deciding the biomes of the pillars
+pub struct BiomeMap {
+    frequency: f64,
+    blend: f64,
+    temperature: OpenSimplex,
+    moisture: OpenSimplex,
+    hills: Fbm<OpenSimplex>,
+    ridges: RidgedMulti<OpenSimplex>,
+}
+
+impl BiomeMap {
+    pub fn new(settings: &BiomeSettings) -> BiomeMap {
+        BiomeMap {
+            frequency: settings.frequency,
+            blend: settings.blend.max(1e-3),
+            temperature: OpenSimplex::new(settings.seed),
+            moisture: OpenSimplex::new(settings.seed.wrapping_add(1)),
+            hills: Fbm::new(settings.seed.wrapping_add(2)),
+            ridges: RidgedMulti::new(settings.seed.wrapping_add(3)),
+        }
+    }
+
+    /// Weight of each of `Biome::ALL` at the pillar, summing up to 1. The weights fall off with
+    /// the distance of the local climate to the climate of the biome, so the closest biome
+    /// dominates and borders blend smoothly.
+    pub fn weights(&self, p: [i32; 2]) -> [f64; 3] {
+        let q = [p[0] as f64 * self.frequency, p[1] as f64 * self.frequency];
+        let climate = [self.temperature.get(q), self.moisture.get(q)];
+        let distances = Biome::ALL.map(|b| {
+            let c = b.climate();
+            (c[0] - climate[0]).powi(2) + (c[1] - climate[1]).powi(2)
+        });
+        let closest = distances.iter().copied().fold(f64::INFINITY, f64::min);
+        let weights = distances.map(|d| (-(d - closest) / (self.blend * self.blend)).exp());
+        let sum: f64 = weights.iter().sum();
+        weights.map(|w| w / sum)
+    }
+
+    /// the biome with the largest weight
+    pub fn biome(&self, p: [i32; 2]) -> Biome {
+        let weights = self.weights(p);
+        let mut best = 0;
+        for (i, w) in weights.iter().enumerate() {
+            if *w > weights[best] {
+                best = i;
+            }
+        }
+        Biome::ALL[best]
+    }
+
+    /// ground height of the pillar, the biome heights blended by their weights
+    pub fn height(&self, p: [i32; 2]) -> f64 {
+        Biome::ALL
+            .iter()
+            .zip(self.weights(p))
+            .filter(|(_, w)| *w > 1e-4)
+            .map(|(b, w)| w * self.biome_height(*b, p))
+            .sum()
+    }
+
+    pub fn water_level(&self, p: [i32; 2]) -> f64 {
+        Biome::ALL
+            .iter()
+            .zip(self.weights(p))
+            .map(|(b, w)| w * b.water_level())
+            .sum()
+    }
+
+    fn biome_height(&self, biome: Biome, p: [i32; 2]) -> f64 {
+        let at = |frequency: f64| [p[0] as f64 * frequency, p[1] as f64 * frequency];
+        match biome {
+            // low dunes
+            Biome::Desert => 20.0 + 4.0 * self.hills.get(at(0.03)),
+            // wide rolling hills
+            Biome::Plains => 30.0 + 6.0 * self.hills.get(at(0.008)),
+            // sharp ridges
+            Biome::Mountains => 36.0 + 24.0 * self.ridges.get(at(0.01)),
+        }
+    }
+}
+
 impl VoxelWorld {
     pub fn gen(xs: Range<i8>, zs: Range<i8>) -> VoxelWorld {
+        VoxelWorld::generate(xs, zs, &GenSettings::default())
+    }
+
+    pub fn generate(xs: Range<i8>, zs: Range<i8>, settings: &GenSettings) -> VoxelWorld {
         let mut ground: ChunkStorage = HashMap::new();
         let n = Fbm::<OpenSimplex>::new(0);
+        let biomes = settings.biomes.as_ref().map(BiomeMap::new);
         for x in xs.clone() {
             for z in zs.clone() {
                 let mut c = VoxelStorage::empty();
                 for lx in 0..64 {
                     for lz in 0..64 {
-                        let height = (n.get(VoxelWorld::to_noise([
-                            (x as i32) * 64 + (lx as i32),
-                            (z as i32) * 64 + (lz as i32),
-                        ])) + 1.0)
-                            / 2.0
-                            * 64.0
-                            + 1.0;
+                        let p = [(x as i32) * 64 + (lx as i32), (z as i32) * 64 + (lz as i32)];
+                        let height = match &biomes {
+                            Some(biomes) => biomes.height(p).clamp(1.0, 64.0),
+                            None => (n.get(VoxelWorld::to_noise(p)) + 1.0) / 2.0 * 64.0 + 1.0,
+                        };
                         let mut y = 0;
                         while (y as f64) < height {
                             c.set([lx, y, lz]);
@@ -37,14 +194,30 @@ impl VoxelWorld {
                 ground.insert([x, z], c);
             }
         }
+        if let Some(erosion) = &settings.erosion {
+            erode(&xs, &zs, &mut ground, erosion);
+        }
+        if let Some(caves) = &settings.caves {
+            carve_caves(&mut ground, caves);
+        }
+        let mut lakes = settings
+            .rivers
+            .as_ref()
+            .map(|rivers| rivers_and_lakes(&xs, &zs, &mut ground, rivers));
+        let rain = biomes.is_none() && lakes.is_none();
         let mut water: ChunkStorage = HashMap::new();
         for x in xs.clone() {
             for z in zs.clone() {
-                let mut c = VoxelStorage::empty();
+                let mut c = lakes
+                    .as_mut()
+                    .and_then(|lakes| lakes.remove(&[x, z]))
+                    .unwrap_or_else(VoxelStorage::empty);
                 for lx in 0..64 {
                     for lz in 0..64 {
-                        for y in 62..63 {
-                            c.set([lx, y, lz]);
+                        if let Some(biomes) = &biomes {
+                            let p = [(x as i32) * 64 + (lx as i32), (z as i32) * 64 + (lz as i32)];
+                            let level = biomes.water_level(p).clamp(0.0, 63.0) as u32;
+                            c.set_pillar([lx, lz], c.get_pillar([lx, lz]) | low_bits(level));
                         }
                     }
                 }
@@ -52,6 +225,40 @@ impl VoxelWorld {
                 water.insert([x, z], c);
             }
         }
+        let mut world = VoxelWorld {
+            ground,
+            water,
+            xs,
+            zs,
+        };
+        if let Some(prefabs) = &settings.prefabs {
+            place_prefabs(&mut world, prefabs);
+        }
+        if rain {
+            for (coord, c) in world.water.iter_mut() {
+                for lx in 0..64 {
+                    for lz in 0..64 {
+                        for y in 62..63 {
+                            c.set([lx, y, lz]);
+                        }
+                    }
+                }
+                c.subtract(&world.ground[coord]);
+            }
+        }
+        world
+    }
+
+    /// world without any ground or water, all chunks are allocated
+    pub fn empty(xs: Range<i8>, zs: Range<i8>) -> VoxelWorld {
+        let mut ground: ChunkStorage = HashMap::new();
+        let mut water: ChunkStorage = HashMap::new();
+        for x in xs.clone() {
+            for z in zs.clone() {
+                ground.insert([x, z], VoxelStorage::empty());
+                water.insert([x, z], VoxelStorage::empty());
+            }
+        }
         VoxelWorld {
             ground,
             water,
@@ -63,6 +270,50 @@ impl VoxelWorld {
     fn to_noise(g: [i32; 2]) -> [f64; 2] {
         [g[0] as f64 * 0.01, g[1] as f64 * 0.01]
     }
+
+    /// splits a voxel position in world space into the chunk and the position inside of the chunk
+    /// returns None for positions outside of the generated chunks or the height range
+    pub fn locate(&self, p: [i32; 3]) -> Option<([i8; 2], [u8; 3])> {
+        if !(0..64).contains(&p[1]) {
+            return None;
+        }
+        let chunk = [
+            i8::try_from(p[0].div_euclid(64)).ok()?,
+            i8::try_from(p[2].div_euclid(64)).ok()?,
+        ];
+        if !self.xs.contains(&chunk[0]) || !self.zs.contains(&chunk[1]) {
+            return None;
+        }
+        Some((
+            chunk,
+            [
+                p[0].rem_euclid(64) as u8,
+                p[1] as u8,
+                p[2].rem_euclid(64) as u8,
+            ],
+        ))
+    }
+
+    /// voxel position in world space containing the given point
+    pub fn voxel_at(p: [f32; 3]) -> [i32; 3] {
+        [
+            p[0].floor() as i32,
+            p[1].floor() as i32,
+            p[2].floor() as i32,
+        ]
+    }
+
+    pub fn is_ground(&self, p: [i32; 3]) -> bool {
+        self.locate(p)
+            .map(|(chunk, local)| self.ground[&chunk].get(local))
+            .unwrap_or(false)
+    }
+
+    pub fn is_water(&self, p: [i32; 3]) -> bool {
+        self.locate(p)
+            .map(|(chunk, local)| self.water[&chunk].get(local))
+            .unwrap_or(false)
+    }
 }
 
 pub struct VoxelStorage {
@@ -85,6 +336,12 @@ impl VoxelStorage {
         let ground = ground | ground_pattern;
         self.raw[grid_positon as usize] = ground;
     }
+    pub fn unset(&mut self, coords: [u8; 3]) {
+        let lin = linearize_position(coords);
+        let height = extract_height(lin);
+        let grid_positon = extract_grid_index(lin);
+        self.raw[grid_positon as usize] &= !(1u64 << height);
+    }
     pub fn get(&self, coords: [u8; 3]) -> bool {
         let lin = linearize_position(coords);
         let height = extract_height(lin);
@@ -137,7 +394,7 @@ impl VoxelStorage {
                 }
                 let top_most = (column & 1 << 63) != 0;
                 if top_most {
-                    faces.top.push([x as u8, 0, z as u8]);
+                    faces.top.push([x as u8, 63, z as u8]);
                 }
 
                 // left
@@ -237,6 +494,52 @@ impl VoxelStorage {
         }
     }
 
+    /// Faces of the water in `self` that border air: faces against water or `ground` are left out,
+    /// as is the bottom of the world. `outside` tells whether a voxel beyond the chunk border,
+    /// relative to the chunk, holds water or ground.
+    pub fn surface_faces(
+        &self,
+        ground: &VoxelStorage,
+        outside: impl Fn([i32; 3]) -> bool,
+    ) -> Faces {
+        let mut faces = Faces::empty();
+        let filled = |p: [i32; 2]| -> u64 {
+            if (0..64).contains(&p[0]) && (0..64).contains(&p[1]) {
+                let i = p[0] as usize + p[1] as usize * 64;
+                self.raw[i] | ground.raw[i]
+            } else {
+                (0..64)
+                    .filter(|y| outside([p[0], *y, p[1]]))
+                    .fold(0, |pillar, y| pillar | 1 << y)
+            }
+        };
+        for z in 0..64i32 {
+            for x in 0..64i32 {
+                let water = self.raw[x as usize + z as usize * 64];
+                if water == 0 {
+                    continue;
+                }
+                let column = filled([x, z]);
+                let sides = [
+                    (water & !(column >> 1), &mut faces.top),
+                    (water & !(column << 1) & !1, &mut faces.bottom),
+                    (water & !filled([x - 1, z]), &mut faces.left),
+                    (water & !filled([x + 1, z]), &mut faces.right),
+                    (water & !filled([x, z - 1]), &mut faces.front),
+                    (water & !filled([x, z + 1]), &mut faces.back),
+                ];
+                for (visible, dst) in sides {
+                    for y in 0..64u8 {
+                        if (visible >> y) & 1 == 1 {
+                            dst.push([x as u8, y, z as u8]);
+                        }
+                    }
+                }
+            }
+        }
+        faces
+    }
+
     fn left(p: [u8; 2]) -> Option<[u8; 2]> {
         if p[0] == 0 {
             None
@@ -281,7 +584,7 @@ pub struct Faces {
 }
 
 impl Faces {
-    fn empty() -> Faces {
+    pub fn empty() -> Faces {
         Faces {
             top: Vec::new(),
             bottom: Vec::new(),
@@ -315,6 +618,15 @@ fn extract_height(p: u32) -> u8 {
     (p >> 12) as u8
 }
 
+/// pillar with the lowest `n` cells set
+pub fn low_bits(n: u32) -> u64 {
+    if n >= 64 {
+        u64::MAX
+    } else {
+        (1 << n) - 1
+    }
+}
+
 fn extract_grid_index(p: u32) -> u32 {
     p & 0b111111111111
 }
@@ -328,7 +640,52 @@ fn linearize_position(index: [u8; 3]) -> u32 {
 
 #[cfg(test)]
 mod test {
-    use super::{delinearize_position, linearize_position, VoxelStorage};
+    use super::{
+        delinearize_position, linearize_position, Biome, BiomeMap, BiomeSettings, GenSettings,
+        VoxelStorage, VoxelWorld,
+    };
+
+    #[test]
+    fn biomes_blend_smoothly() {
+        let map = BiomeMap::new(&BiomeSettings::default());
+        let mut seen = Vec::new();
+        for z in (-1024..1024).step_by(64) {
+            let mut previous = map.height([-1024, z]);
+            for x in -1023..1024 {
+                let weights = map.weights([x, z]);
+                assert!((weights.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+                let biome = map.biome([x, z]);
+                if !seen.contains(&biome) {
+                    seen.push(biome);
+                }
+                // no cliffs at the borders between biomes
+                let height = map.height([x, z]);
+                assert!((height - previous).abs() < 4.0, "{x} {z}");
+                previous = height;
+            }
+        }
+        assert_eq!(seen.len(), Biome::ALL.len());
+    }
+
+    #[test]
+    fn deserts_stay_dry() {
+        let settings = GenSettings {
+            biomes: Some(BiomeSettings::default()),
+            ..Default::default()
+        };
+        let world = VoxelWorld::generate(-2..2, -2..2, &settings);
+        let map = BiomeMap::new(&BiomeSettings::default());
+        for x in -128..128 {
+            for z in -128..128 {
+                let height = map.height([x, z]).clamp(1.0, 64.0).ceil() as i32;
+                assert!(world.is_ground([x, height - 1, z]));
+                assert!(!world.is_ground([x, height, z]));
+                if map.weights([x, z])[0] > 0.99 {
+                    assert!((0..64).all(|y| !world.is_water([x, y, z])));
+                }
+            }
+        }
+    }
 
     #[test]
     fn check_position_conversion() {
@@ -389,6 +746,40 @@ mod test {
         assert_eq!(faces.front.len(), 64 * 64);
         assert_eq!(faces.front.len(), 64 * 64);
     }
+
+    #[test]
+    fn water_surface_faces() {
+        let mut ground = VoxelStorage::empty();
+        let mut water = VoxelStorage::empty();
+        // a pool of 2 x 2 x 2 water in a ground basin, the rim is one cell higher than the water
+        for x in 0..4 {
+            for z in 0..4 {
+                ground.set([x, 0, z]);
+                for y in 1..4 {
+                    if (1..3).contains(&x) && (1..3).contains(&z) {
+                        if y < 3 {
+                            water.set([x, y, z]);
+                        }
+                    } else {
+                        ground.set([x, y, z]);
+                    }
+                }
+            }
+        }
+        let faces = water.surface_faces(&ground, |_| false);
+        assert_eq!(faces.total(), 4);
+        assert!(faces.top.iter().all(|f| f[1] == 2));
+
+        // water at the top of the world and beside the chunk border
+        let mut water = VoxelStorage::empty();
+        water.set([0, 63, 0]);
+        let open = water.surface_faces(&VoxelStorage::empty(), |_| false);
+        assert_eq!(open.top, vec![[0, 63, 0]]);
+        assert_eq!(open.total(), 6);
+        let neighbour = water.surface_faces(&VoxelStorage::empty(), |p| p[0] < 0);
+        assert!(neighbour.left.is_empty());
+        assert_eq!(neighbour.total(), 5);
+    }
 }
 
 // fn create_voxels() -> (PackedVector3Array, PackedInt32Array) {
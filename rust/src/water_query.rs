@@ -0,0 +1,104 @@
+use crate::voxel_storage::VoxelWorld;
+use crate::water_sim::FlowTracker;
+
+/// water at a point, all values are in voxel units relative to the world origin
+pub struct WaterSample {
+    /// the point is inside of a water voxel
+    pub submerged: bool,
+    /// top of the water body containing the point or, if the point is above water, of the one below it
+    /// None if there is no water below the point or it is covered by ground
+    pub surface_height: Option<f32>,
+    /// distance from the point down to the surface, negative above the surface, 0 without a surface
+    pub depth: f32,
+    /// average flow of the pillar in cells per step, x and z
+    pub flow: [f32; 2],
+}
+
+pub fn sample_water(world: &VoxelWorld, flow: &FlowTracker, p: [f32; 3]) -> WaterSample {
+    let mut sample = WaterSample {
+        submerged: false,
+        surface_height: None,
+        depth: 0.0,
+        flow: [0.0; 2],
+    };
+    let voxel = VoxelWorld::voxel_at(p);
+    if voxel[1] < 0 {
+        return sample;
+    }
+    let Some((chunk, local)) = world.locate([voxel[0], voxel[1].min(63), voxel[2]]) else {
+        return sample;
+    };
+    let water = world.water[&chunk].get_pillar([local[0], local[2]]);
+    let ground = world.ground[&chunk].get_pillar([local[0], local[2]]);
+    let y = voxel[1] as u32;
+
+    let surface = if y < 64 && (water >> y) & 1 == 1 {
+        sample.submerged = true;
+        Some(y + (water >> y).trailing_ones())
+    } else {
+        let below = if y >= 64 { u64::MAX } else { (1u64 << y) - 1 };
+        let candidates = water & below;
+        if candidates == 0 {
+            None
+        } else {
+            let top = 64 - candidates.leading_zeros();
+            let between = below & u64::MAX.checked_shl(top).unwrap_or(0);
+            if ground & between != 0 {
+                None
+            } else {
+                Some(top)
+            }
+        }
+    };
+    if let Some(surface) = surface {
+        sample.surface_height = Some(surface as f32);
+        sample.depth = surface as f32 - p[1];
+    }
+    if let Some(field) = flow.get(chunk) {
+        sample.flow = field.sample([local[0], local[2]]);
+    }
+    sample
+}
+
+#[cfg(test)]
+mod test {
+    use crate::voxel_storage::VoxelWorld;
+    use crate::water_sim::FlowTracker;
+
+    use super::sample_water;
+
+    #[test]
+    fn sample_surface_and_depth() {
+        let mut world = VoxelWorld::empty(-1..1, 0..1);
+        let ground = world.ground.get_mut(&[-1, 0]).unwrap();
+        ground.set([10, 0, 5]);
+        ground.set([20, 8, 5]);
+        let water = world.water.get_mut(&[-1, 0]).unwrap();
+        for y in 1..4 {
+            water.set([10, y, 5]);
+            water.set([20, y, 5]);
+        }
+        let flow = FlowTracker::new(1);
+
+        let inside = sample_water(&world, &flow, [-53.5, 2.5, 5.5]);
+        assert!(inside.submerged);
+        assert_eq!(inside.surface_height, Some(4.0));
+        assert_eq!(inside.depth, 1.5);
+
+        let above = sample_water(&world, &flow, [-53.5, 6.0, 5.5]);
+        assert!(!above.submerged);
+        assert_eq!(above.surface_height, Some(4.0));
+        assert_eq!(above.depth, -2.0);
+
+        let high_above = sample_water(&world, &flow, [-53.5, 100.0, 5.5]);
+        assert_eq!(high_above.surface_height, Some(4.0));
+
+        let covered = sample_water(&world, &flow, [-43.5, 10.0, 5.5]);
+        assert!(!covered.submerged);
+        assert_eq!(covered.surface_height, None);
+
+        let outside = sample_water(&world, &flow, [100.0, 2.0, 5.5]);
+        assert!(!outside.submerged);
+        assert_eq!(outside.surface_height, None);
+    }
+}
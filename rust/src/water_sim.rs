@@ -1,6 +1,113 @@
+use std::collections::{HashMap, VecDeque};
+
 use crate::voxel_storage::{ChunkStorage, VoxelStorage, VoxelWorld};
 
-pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) {
+/// Net horizontal water movement of a single chunk, one entry per pillar (x + z * 64).
+/// Each entry counts the cells that left the pillar in x and z direction.
+pub struct FlowField {
+    history: VecDeque<Vec<[i16; 2]>>,
+    sum: Vec<[i32; 2]>,
+}
+
+impl FlowField {
+    fn empty() -> FlowField {
+        FlowField {
+            history: VecDeque::new(),
+            sum: vec![[0; 2]; 64 * 64],
+        }
+    }
+
+    /// average movement per step of the pillar over the recorded window, x and z component
+    pub fn sample(&self, coords: [u8; 2]) -> [f32; 2] {
+        if self.history.is_empty() {
+            return [0.0; 2];
+        }
+        let [x, z] = self.sum[coords[0] as usize + coords[1] as usize * 64];
+        let steps = self.history.len() as f32;
+        [x as f32 / steps, z as f32 / steps]
+    }
+
+    /// row major (x + z * 64) copy of `sample` for all pillars
+    pub fn samples(&self) -> Vec<[f32; 2]> {
+        let mut r = Vec::with_capacity(64 * 64);
+        for z in 0..64 {
+            for x in 0..64 {
+                r.push(self.sample([x, z]));
+            }
+        }
+        r
+    }
+}
+
+/// Collects the `water_flow` masks of `simulate_water_tracked` over the last `window` steps.
+pub struct FlowTracker {
+    pub window: usize,
+    pub chunks: HashMap<[i8; 2], FlowField>,
+}
+
+impl FlowTracker {
+    pub fn new(window: usize) -> FlowTracker {
+        FlowTracker {
+            window: window.max(1),
+            chunks: HashMap::new(),
+        }
+    }
+
+    fn begin_step(&mut self, world: &VoxelWorld) {
+        for i in world.ground.keys() {
+            let field = self.chunks.entry(*i).or_insert_with(FlowField::empty);
+            while field.history.len() >= self.window {
+                let oldest = field.history.pop_front().unwrap();
+                for (s, o) in field.sum.iter_mut().zip(oldest.iter()) {
+                    s[0] -= o[0] as i32;
+                    s[1] -= o[1] as i32;
+                }
+            }
+            field.history.push_back(vec![[0; 2]; 64 * 64]);
+        }
+    }
+
+    fn record(&mut self, chunk: [i8; 2], coords: [u8; 2], direction: [i8; 2], water_flow: u64) {
+        if water_flow == 0 {
+            return;
+        }
+        let field = self.chunks.get_mut(&chunk).unwrap();
+        let index = coords[0] as usize + coords[1] as usize * 64;
+        let moved = water_flow.count_ones() as i16;
+        let current = field.history.back_mut().unwrap();
+        for axis in 0..2 {
+            let delta = moved * direction[axis] as i16;
+            current[index][axis] += delta;
+            field.sum[index][axis] += delta as i32;
+        }
+    }
+
+    pub fn get(&self, chunk: [i8; 2]) -> Option<&FlowField> {
+        self.chunks.get(&chunk)
+    }
+}
+
+/// returns the number of cells that moved
+pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) -> u64 {
+    simulate_water_inner(chunks, step_counter, None)
+}
+
+/// same as `simulate_water`, additionally records the horizontal movement into `flow`
+pub fn simulate_water_tracked(
+    chunks: &mut VoxelWorld,
+    step_counter: u8,
+    flow: &mut FlowTracker,
+) -> u64 {
+    flow.begin_step(chunks);
+    simulate_water_inner(chunks, step_counter, Some(flow))
+}
+
+fn simulate_water_inner(
+    chunks: &mut VoxelWorld,
+    step_counter: u8,
+    mut flow: Option<&mut FlowTracker>,
+) -> u64 {
+    let mut moved = 0u64;
     let mut new_water = ChunkStorage::new();
     for (i, ground) in chunks.ground.iter() {
         let water = &chunks.water[i];
@@ -11,12 +118,15 @@ pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) {
             .zip(water.raw.iter())
             .zip(new_water_element.raw.iter_mut())
         {
+            // the bottom cell can not fall any further
+            *new_water_column = water_column & 1;
             for offset in 1..64 {
                 let cell_selector = 1u64 << offset;
                 let water_cell = water_column & cell_selector;
                 let down_water_cell = water_cell >> 1;
                 let condition =
                     (!ground_column & down_water_cell) & (!*new_water_column & down_water_cell);
+                moved += (down_water_cell & condition).count_ones() as u64;
                 *new_water_column |= (down_water_cell & condition)
                     | (((!condition << 1) & cell_selector) & water_cell);
             }
@@ -24,9 +134,12 @@ pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) {
         new_water.insert(i.clone(), new_water_element);
     }
 
+    // chunks exchange water at their borders, a fixed order keeps the result deterministic
+    let mut order: Vec<[i8; 2]> = chunks.ground.keys().copied().collect();
+    order.sort();
     let step_counter = step_counter % 8;
     if step_counter < 2 {
-        for (i, ground) in chunks.ground.iter() {
+        for (i, ground) in order.iter().map(|i| (i, &chunks.ground[i])) {
             {
                 let water = new_water.get_mut(i).unwrap();
                 for x in 1..64 {
@@ -36,10 +149,14 @@ pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) {
                         let left_free = (!left_ground_column) & (!left_water_column);
                         let current_water = water.get_pillar([x, z]);
                         let water_flow = left_free & current_water;
+                        moved += water_flow.count_ones() as u64;
                         let left_new_water = left_water_column | water_flow;
                         let new_water = current_water & (!water_flow);
                         water.set_pillar([x - 1, z], left_new_water);
                         water.set_pillar([x, z], new_water);
+                        if let Some(flow) = flow.as_deref_mut() {
+                            flow.record(*i, [x, z], [-1, 0], water_flow);
+                        }
                     }
                 }
             } // edge
@@ -56,10 +173,14 @@ pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) {
                         let left_free = (!left_ground_column) & (!left_water_column);
                         let current_water = current_water.get_pillar([0, z]);
                         let water_flow = left_free & current_water;
+                        moved += water_flow.count_ones() as u64;
                         let left_new_water = left_water_column | water_flow;
                         let new_water = current_water & (!water_flow);
                         left_results[z as usize] = left_new_water;
                         current_results[z as usize] = new_water;
+                        if let Some(flow) = flow.as_deref_mut() {
+                            flow.record(*i, [0, z], [-1, 0], water_flow);
+                        }
                     }
                 }
                 {
@@ -77,7 +198,7 @@ pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) {
             }
         }
     } else if step_counter < 4 {
-        for (i, ground) in chunks.ground.iter() {
+        for (i, ground) in order.iter().map(|i| (i, &chunks.ground[i])) {
             let water = new_water.get_mut(i).unwrap();
             for x in 0..63 {
                 for z in 0..64 {
@@ -86,10 +207,14 @@ pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) {
                     let left_free = (!left_ground_column) & (!left_water_column);
                     let current_water = water.get_pillar([x, z]);
                     let water_flow = left_free & current_water;
+                    moved += water_flow.count_ones() as u64;
                     let left_new_water = left_water_column | water_flow;
                     let new_water = current_water & (!water_flow);
                     water.set_pillar([x + 1, z], left_new_water);
                     water.set_pillar([x, z], new_water);
+                    if let Some(flow) = flow.as_deref_mut() {
+                        flow.record(*i, [x, z], [1, 0], water_flow);
+                    }
                 }
             }
 
@@ -106,10 +231,14 @@ pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) {
                         let left_free = (!left_ground_column) & (!left_water_column);
                         let current_water = current_water.get_pillar([63, z]);
                         let water_flow = left_free & current_water;
+                        moved += water_flow.count_ones() as u64;
                         let left_new_water = left_water_column | water_flow;
                         let new_water = current_water & (!water_flow);
                         left_results[z as usize] = left_new_water;
                         current_results[z as usize] = new_water;
+                        if let Some(flow) = flow.as_deref_mut() {
+                            flow.record(*i, [63, z], [1, 0], water_flow);
+                        }
                     }
                 }
                 {
@@ -127,7 +256,7 @@ pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) {
             }
         }
     } else if step_counter < 6 {
-        for (i, ground) in chunks.ground.iter() {
+        for (i, ground) in order.iter().map(|i| (i, &chunks.ground[i])) {
             let water = new_water.get_mut(i).unwrap();
             for x in 0..64 {
                 for z in 1..64 {
@@ -136,10 +265,14 @@ pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) {
                     let left_free = (!left_ground_column) & (!left_water_column);
                     let current_water = water.get_pillar([x, z]);
                     let water_flow = left_free & current_water;
+                    moved += water_flow.count_ones() as u64;
                     let left_new_water = left_water_column | water_flow;
                     let new_water = current_water & (!water_flow);
                     water.set_pillar([x, z - 1], left_new_water);
                     water.set_pillar([x, z], new_water);
+                    if let Some(flow) = flow.as_deref_mut() {
+                        flow.record(*i, [x, z], [0, -1], water_flow);
+                    }
                 }
             }
             if i[1] > chunks.zs.start {
@@ -155,10 +288,14 @@ pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) {
                         let left_free = (!left_ground_column) & (!left_water_column);
                         let current_water = current_water.get_pillar([x, 0]);
                         let water_flow = left_free & current_water;
+                        moved += water_flow.count_ones() as u64;
                         let left_new_water = left_water_column | water_flow;
                         let new_water = current_water & (!water_flow);
                         left_results[x as usize] = left_new_water;
                         current_results[x as usize] = new_water;
+                        if let Some(flow) = flow.as_deref_mut() {
+                            flow.record(*i, [x, 0], [0, -1], water_flow);
+                        }
                     }
                 }
                 {
@@ -176,19 +313,23 @@ pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) {
             }
         }
     } else {
-        for (i, ground) in chunks.ground.iter() {
+        for (i, ground) in order.iter().map(|i| (i, &chunks.ground[i])) {
             let water = new_water.get_mut(i).unwrap();
             for x in 0..64 {
                 for z in 0..63 {
-                    let left_ground_column = ground.get_pillar([x + 1, z]);
-                    let left_water_column = water.get_pillar([x + 1, z]);
+                    let left_ground_column = ground.get_pillar([x, z + 1]);
+                    let left_water_column = water.get_pillar([x, z + 1]);
                     let left_free = (!left_ground_column) & (!left_water_column);
                     let current_water = water.get_pillar([x, z]);
                     let water_flow = left_free & current_water;
+                    moved += water_flow.count_ones() as u64;
                     let left_new_water = left_water_column | water_flow;
                     let new_water = current_water & (!water_flow);
-                    water.set_pillar([x + 1, z], left_new_water);
+                    water.set_pillar([x, z + 1], left_new_water);
                     water.set_pillar([x, z], new_water);
+                    if let Some(flow) = flow.as_deref_mut() {
+                        flow.record(*i, [x, z], [0, 1], water_flow);
+                    }
                 }
             }
             if i[1] < chunks.zs.end - 1 {
@@ -204,10 +345,14 @@ pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) {
                         let left_free = (!left_ground_column) & (!left_water_column);
                         let current_water = current_water.get_pillar([x, 63]);
                         let water_flow = left_free & current_water;
+                        moved += water_flow.count_ones() as u64;
                         let left_new_water = left_water_column | water_flow;
                         let new_water = current_water & (!water_flow);
                         left_results[x as usize] = left_new_water;
                         current_results[x as usize] = new_water;
+                        if let Some(flow) = flow.as_deref_mut() {
+                            flow.record(*i, [x, 63], [0, 1], water_flow);
+                        }
                     }
                 }
                 {
@@ -226,13 +371,124 @@ pub fn simulate_water(chunks: &mut VoxelWorld, step_counter: u8) {
         }
     }
     chunks.water = new_water;
+    moved
 }
 
 #[cfg(test)]
 mod test {
-    use crate::voxel_storage::VoxelWorld;
+    use std::collections::HashMap;
+
+    use proptest::prelude::*;
+
+    use crate::voxel_storage::{VoxelStorage, VoxelWorld};
+
+    use super::{simulate_water, simulate_water_tracked, FlowTracker};
+
+    /// World of `xs` by `zs` chunks, `heights` and `water` hold the ground height and a water
+    /// mask per pillar, chunk after chunk and `x + 64 * z` within a chunk. Water inside the
+    /// ground is dropped.
+    fn world(xs: i8, zs: i8, heights: &[u8], water: &[u64]) -> VoxelWorld {
+        let mut world = VoxelWorld::empty(0..xs, 0..zs);
+        let mut pillars = heights.iter().zip(water.iter());
+        for x in 0..xs {
+            for z in 0..zs {
+                let ground = world.ground.get_mut(&[x, z]).unwrap();
+                let water = world.water.get_mut(&[x, z]).unwrap();
+                for lz in 0..64 {
+                    for lx in 0..64 {
+                        let (height, mask) = pillars.next().unwrap();
+                        let pillar = (1u64 << height) - 1;
+                        ground.set_pillar([lx, lz], pillar);
+                        water.set_pillar([lx, lz], mask & !pillar);
+                    }
+                }
+            }
+        }
+        world
+    }
+
+    fn total(world: &VoxelWorld) -> u64 {
+        world.water.values().map(|c| c.count()).sum()
+    }
+
+    /// chunk counts, ground heights, water masks and the first step counter
+    fn random_world() -> impl Strategy<Value = (i8, i8, Vec<u8>, Vec<u64>, u8)> {
+        (1..3i8, 1..3i8).prop_flat_map(|(xs, zs)| {
+            let pillars = xs as usize * zs as usize * 64 * 64;
+            (
+                Just(xs),
+                Just(zs),
+                prop::collection::vec(0..64u8, pillars),
+                prop::collection::vec(any::<u64>(), pillars),
+                any::<u8>(),
+            )
+        })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(16))]
+
+        /// The world border acts as a wall, so no water may leave the world and the volume stays
+        /// the same in every step, running through all eight phases twice.
+        #[test]
+        fn volume_is_conserved((xs, zs, heights, water, first) in random_world()) {
+            let mut world = world(xs, zs, &heights, &water);
+            let volume = total(&world);
+            for step in 0..16u8 {
+                simulate_water(&mut world, first.wrapping_add(step));
+                prop_assert_eq!(total(&world), volume);
+                prop_assert_eq!(world.water.len(), (xs as usize) * (zs as usize));
+            }
+        }
+
+        #[test]
+        fn water_never_overlaps_ground((xs, zs, heights, water, first) in random_world()) {
+            let mut world = world(xs, zs, &heights, &water);
+            for step in 0..16u8 {
+                simulate_water(&mut world, first.wrapping_add(step));
+                for (i, ground) in world.ground.iter() {
+                    let water = &world.water[i];
+                    for (g, w) in ground.raw.iter().zip(water.raw.iter()) {
+                        prop_assert_eq!(g & w, 0);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn simulation_is_deterministic((xs, zs, heights, water, first) in random_world()) {
+            let mut a = world(xs, zs, &heights, &water);
+            let mut b = world(xs, zs, &heights, &water);
+            for step in 0..16u8 {
+                let moved_a = simulate_water(&mut a, first.wrapping_add(step));
+                let moved_b = simulate_water(&mut b, first.wrapping_add(step));
+                prop_assert_eq!(moved_a, moved_b);
+            }
+            for (i, water) in a.water.iter() {
+                prop_assert_eq!(&water.raw, &b.water[i].raw);
+            }
+        }
+    }
+
+    #[test]
+    fn bottom_cell_is_kept() {
+        let mut world = world(1, 1, &[0; 64 * 64], &[0b11; 64 * 64]);
+        simulate_water(&mut world, 0);
+        assert_eq!(total(&world), 2 * 64 * 64);
+        assert_eq!(world.water[&[0, 0]].get_pillar([5, 5]), 0b11);
+    }
 
-    use super::simulate_water;
+    #[test]
+    fn flows_towards_positive_z() {
+        let mut heights = vec![0; 64 * 64];
+        heights[10 + 12 * 64] = 1;
+        let mut water = vec![0; 64 * 64];
+        water[10 + 10 * 64] = 1;
+        let mut world = world(1, 1, &heights, &water);
+        simulate_water(&mut world, 6);
+        assert!(world.water[&[0, 0]].get([10, 0, 11]));
+        assert_eq!(total(&world), 1);
+    }
 
     #[test]
     fn water_amount_stays_constant() {
@@ -246,4 +502,33 @@ mod test {
             assert_eq!(total_water, water_after);
         }
     }
+
+    #[test]
+    fn flow_field_records_movement() {
+        let mut ground = VoxelStorage::empty();
+        for x in 0..64 {
+            for z in 0..64 {
+                ground.set([x, 0, z]);
+            }
+        }
+        let mut water = VoxelStorage::empty();
+        water.set([10, 1, 10]);
+        let mut world = VoxelWorld {
+            xs: 0..1,
+            zs: 0..1,
+            ground: HashMap::from([([0, 0], ground)]),
+            water: HashMap::from([([0, 0], water)]),
+        };
+        let mut flow = FlowTracker::new(2);
+        simulate_water_tracked(&mut world, 0, &mut flow);
+        assert!(world.water[&[0, 0]].get([9, 1, 10]));
+        let field = flow.get([0, 0]).unwrap();
+        assert_eq!(field.sample([10, 10]), [-1.0, 0.0]);
+
+        simulate_water_tracked(&mut world, 4, &mut flow);
+        simulate_water_tracked(&mut world, 4, &mut flow);
+        let field = flow.get([0, 0]).unwrap();
+        assert_eq!(field.sample([10, 10]), [0.0, 0.0]);
+        assert_eq!(field.sample([9, 10]), [0.0, -0.5]);
+    }
 }
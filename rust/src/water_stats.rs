@@ -0,0 +1,107 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use crate::components::label_water;
+use crate::voxel_storage::VoxelWorld;
+
+/// connected body of water cells, connected through faces, also across chunk borders
+pub struct Basin {
+    pub volume: u64,
+    /// height of the top of the highest water cell of the basin
+    pub surface_level: u8,
+}
+
+pub struct WaterStatistics {
+    pub total_volume: u64,
+    pub chunk_volume: HashMap<[i8; 2], u64>,
+    /// cells moved by the last simulation step
+    pub moving_cells: u64,
+    /// ordered by volume, largest first
+    pub basins: Vec<Basin>,
+}
+
+impl WaterStatistics {
+    pub fn collect(world: &VoxelWorld, moving_cells: u64) -> WaterStatistics {
+        let chunk_volume: HashMap<[i8; 2], u64> =
+            world.water.iter().map(|(i, w)| (*i, w.count())).collect();
+        let mut basins = find_basins(world);
+        basins.sort_by_key(|b| Reverse(b.volume));
+        WaterStatistics {
+            total_volume: chunk_volume.values().sum(),
+            chunk_volume,
+            moving_cells,
+            basins,
+        }
+    }
+
+    /// multi line summary for debug displays, lists at most `max_basins` basins
+    pub fn summary(&self, max_basins: usize) -> String {
+        let mut r = format!(
+            "water: {}\nmoving: {}\nbasins: {}\n",
+            self.total_volume,
+            self.moving_cells,
+            self.basins.len()
+        );
+        for (i, basin) in self.basins.iter().take(max_basins).enumerate() {
+            r.push_str(&format!(
+                "  #{i}: volume {} level {}\n",
+                basin.volume, basin.surface_level
+            ));
+        }
+        r
+    }
+}
+
+fn find_basins(world: &VoxelWorld) -> Vec<Basin> {
+    label_water(world)
+        .components
+        .iter()
+        .map(|c| Basin {
+            volume: c.volume,
+            surface_level: c.max_y + 1,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::voxel_storage::VoxelWorld;
+    use crate::water_sim::simulate_water;
+
+    use super::WaterStatistics;
+
+    #[test]
+    fn basins_across_chunk_borders() {
+        let mut world = VoxelWorld::empty(0..2, 0..1);
+        for z in 0..4 {
+            world.water.get_mut(&[0, 0]).unwrap().set([63, 3, z]);
+            world.water.get_mut(&[1, 0]).unwrap().set([0, 3, z]);
+            world.water.get_mut(&[1, 0]).unwrap().set([0, 4, z]);
+        }
+        world.water.get_mut(&[0, 0]).unwrap().set([10, 0, 10]);
+
+        let stats = WaterStatistics::collect(&world, 0);
+        assert_eq!(stats.total_volume, 13);
+        assert_eq!(stats.chunk_volume[&[0, 0]], 5);
+        assert_eq!(stats.chunk_volume[&[1, 0]], 8);
+        assert_eq!(stats.basins.len(), 2);
+        assert_eq!(stats.basins[0].volume, 12);
+        assert_eq!(stats.basins[0].surface_level, 5);
+        assert_eq!(stats.basins[1].volume, 1);
+        assert_eq!(stats.basins[1].surface_level, 1);
+    }
+
+    #[test]
+    fn moving_cells_of_falling_water() {
+        let mut world = VoxelWorld::empty(0..1, 0..1);
+        world.ground.get_mut(&[0, 0]).unwrap().set([6, 0, 5]);
+        let water = world.water.get_mut(&[0, 0]).unwrap();
+        water.set([5, 10, 5]);
+        water.set([5, 11, 5]);
+        water.set([6, 1, 5]);
+        // the two floating cells fall by one, afterwards all three move one pillar to the left
+        let moved = simulate_water(&mut world, 0);
+        let stats = WaterStatistics::collect(&world, moved);
+        assert_eq!(stats.moving_cells, 5);
+        assert_eq!(stats.total_volume, 3);
+    }
+}